@@ -0,0 +1,37 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+// The plugin registers the fields of all messages when Wireshark starts,
+// run it by `cargo bench --bench registration`.
+
+use std::time::{Duration, Instant};
+use tezos_dissector::TezosEncoded;
+use tezos_messages::p2p::encoding::{
+    ack::AckMessage, metadata::MetadataMessage, peer::PeerMessageResponse,
+    connection::ConnectionMessage,
+};
+use wireshark_epan_adapter::dissector::HasFields;
+
+// the start of Wireshark should not be noticeably slower
+const LIMIT: Duration = Duration::from_millis(500);
+
+fn register() -> usize {
+    TezosEncoded::<ConnectionMessage>::fields().len()
+        + TezosEncoded::<MetadataMessage>::fields().len()
+        + TezosEncoded::<AckMessage>::fields().len()
+        + TezosEncoded::<PeerMessageResponse>::fields().len()
+}
+
+fn main() {
+    let start = Instant::now();
+    let count = register();
+    let first = start.elapsed();
+
+    // the fields are cached for each type
+    let start = Instant::now();
+    register();
+    let second = start.elapsed();
+
+    println!("{} fields in {:?}, cached in {:?}", count, first, second);
+    assert!(first < LIMIT, "the registration takes {:?}", first);
+}
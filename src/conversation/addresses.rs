@@ -24,6 +24,13 @@ impl Addresses {
         }
     }
 
+    pub fn address(&self, sender: &Sender) -> &SocketAddress {
+        match sender {
+            &Sender::Initiator => &self.initiator,
+            &Sender::Responder => &self.responder,
+        }
+    }
+
     pub fn sender(&self, packet_info: &PacketInfo) -> Sender {
         if self.initiator == packet_info.source() {
             assert_eq!(self.responder, packet_info.destination());
@@ -42,3 +49,13 @@ pub enum Sender {
     Initiator,
     Responder,
 }
+
+impl Sender {
+    /// The initiator is the local node, as shown in `tezos.source`.
+    pub fn direction(&self) -> &'static str {
+        match self {
+            &Sender::Initiator => "local",
+            &Sender::Responder => "remote",
+        }
+    }
+}
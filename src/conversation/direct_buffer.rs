@@ -1,13 +1,13 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use std::{ops::Range, collections::BTreeMap, cell::Cell};
+use std::{ops::Range, collections::BTreeMap, cell::Cell, borrow::Cow};
 use bytes::Buf;
 use failure::Fail;
-use super::addresses::Sender;
+use super::{addresses::Sender, storage::Storage};
 use crate::{
     identity::{Decipher, NonceAddition},
-    value::HasBodyRange,
+    value::{HasBodyRange, DecodedMessage},
 };
 
 #[derive(Clone)]
@@ -66,11 +66,90 @@ impl HasBodyRange for ChunkInfo {
     }
 }
 
+/// When the frame is captured.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Timestamp {
+    /// Seconds since the UNIX epoch.
+    pub absolute: f64,
+    /// Seconds since the first frame of the capture.
+    pub relative: f64,
+}
+
+/// The decoded message and where it lies in the stream.
+pub struct MessageInfo {
+    pub decoded: DecodedMessage,
+    /// Frames containing at least one byte of chunks of the message, ascending.
+    pub frames: Vec<u64>,
+    /// When the frame which completes the message is captured.
+    pub time: Timestamp,
+    /// Sum of the bodies of the chunks.
+    pub size: usize,
+    // the values are dropped by `DirectBuffer::spill`, see `DirectBuffer::decoded`
+    evicted: bool,
+}
+
+impl MessageInfo {
+    pub fn first_frame(&self) -> u64 {
+        self.frames.first().cloned().unwrap_or_default()
+    }
+
+    pub fn last_frame(&self) -> u64 {
+        self.frames.last().cloned().unwrap_or_default()
+    }
+}
+
+/// The tail of the payload that does not contain a complete chunk,
+/// TCP should deliver it again together with more data.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Desegment {
+    pub offset: usize,
+    /// `None` if even the length of the chunk is not received yet.
+    pub length: Option<usize>,
+}
+
+impl Desegment {
+    /// The payload should start at a chunk boundary.
+    pub fn new(payload: &[u8]) -> Option<Self> {
+        let mut position = 0;
+        loop {
+            if position == payload.len() {
+                break None;
+            } else if position + 2 > payload.len() {
+                break Some(Desegment {
+                    offset: position,
+                    length: None,
+                });
+            } else {
+                let length = (&payload[position..(position + 2)]).get_u16() as usize;
+                let end = position + 2 + length;
+                if end > payload.len() {
+                    break Some(Desegment {
+                        offset: position,
+                        length: Some(end - payload.len()),
+                    });
+                }
+                position = end;
+            }
+        }
+    }
+}
+
+// the payload in the buffer
+struct Packet {
+    range: Range<usize>,
+    time: Timestamp,
+}
+
 pub struct DirectBuffer {
-    data: Vec<u8>,
+    data: Storage,
     chunks: Vec<ChunkInfo>,
-    packets: BTreeMap<u64, Range<usize>>,
+    // the key is the frame and the offset of the payload in its data source,
+    // the frame might have several payloads if TCP reassembles the data
+    packets: BTreeMap<(u64, usize), Packet>,
     processed: usize,
+    messages: Vec<MessageInfo>,
+    // bytes the decoded values of the messages occupy
+    decoded_size: usize,
 }
 
 #[derive(Debug, Fail, Eq, PartialEq)]
@@ -86,24 +165,34 @@ pub struct DecryptError {
 impl DirectBuffer {
     pub fn new() -> Self {
         DirectBuffer {
-            data: Vec::with_capacity(0x100000),
-            chunks: Vec::with_capacity(0x1000),
+            data: Storage::new(),
+            chunks: Vec::new(),
             packets: BTreeMap::new(),
             // first message always decrypted
             processed: 1,
+            messages: Vec::new(),
+            decoded_size: 0,
         }
     }
 
-    pub fn consume(&mut self, payload: &[u8], frame_index: u64) {
+    pub fn consume(&mut self, payload: &[u8], frame_index: u64, offset: usize, time: Timestamp) {
+        // the same payload might be offered twice, by the port table and by the heuristic
+        if self.packets.contains_key(&(frame_index, offset)) {
+            return;
+        }
         let start = self.data.len();
-        self.data.extend_from_slice(payload);
+        self.data.extend(payload);
         let end = self.data.len();
-        self.packets.insert(frame_index, start..end);
+        let packet = Packet {
+            range: start..end,
+            time,
+        };
+        self.packets.insert((frame_index, offset), packet);
         let mut position = self.chunks.last().map(|r| r.range().end).unwrap_or(0);
 
         loop {
             if position + 2 < end {
-                let length = (&self.data[position..(position + 2)]).get_u16() as usize;
+                let length = (&self.data()[position..(position + 2)]).get_u16() as usize;
                 let this_end = position + 2 + length;
                 self.chunks.push(ChunkInfo::new(position, this_end));
                 position = this_end;
@@ -142,6 +231,91 @@ impl DirectBuffer {
         Ok(())
     }
 
+    /// Decode complete messages which are not decoded yet.
+    pub fn decode(&mut self) {
+        // the first chunk is plain, but it might be not received completely
+        let available = match self.chunks.first() {
+            Some(c) if self.data().len() >= c.range().end => {
+                usize::min(self.processed, self.chunks.len())
+            },
+            _ => 0,
+        };
+        let chunks = &self.chunks[..available];
+        loop {
+            let first_chunk = self
+                .messages
+                .last()
+                .map(|m| m.decoded.chunks.end)
+                .unwrap_or(0);
+            if first_chunk >= chunks.len() {
+                break;
+            }
+            match DecodedMessage::decode(self.data.as_ref(), chunks, first_chunk) {
+                Some(decoded) => {
+                    let chunks = &chunks[decoded.chunks.clone()];
+                    chunks[1..].iter().for_each(ChunkInfo::set_continuation);
+                    let start = chunks[0].range().start;
+                    let end = chunks[chunks.len() - 1].range().end;
+                    // frames are consumed in order, so the latest frames are at the end
+                    let packets = self
+                        .packets
+                        .iter()
+                        .rev()
+                        .skip_while(|&(_, p)| p.range.start >= end)
+                        .take_while(|&(_, p)| p.range.end > start)
+                        .map(|(&(frame, _), p)| (frame, p.time))
+                        .collect::<Vec<_>>();
+                    // the latest packet completes the message
+                    let time = packets.first().map(|&(_, time)| time).unwrap_or_default();
+                    let mut frames = packets.into_iter().map(|(f, _)| f).collect::<Vec<_>>();
+                    frames.reverse();
+                    frames.dedup();
+                    let size = chunks.iter().map(|c| c.body().len()).sum();
+                    self.decoded_size += decoded.in_memory();
+                    self.messages.push(MessageInfo {
+                        decoded,
+                        frames,
+                        time,
+                        size,
+                        evicted: false,
+                    });
+                },
+                // wait for more chunks
+                None => break,
+            }
+        }
+    }
+
+    pub fn messages(&self) -> &[MessageInfo] {
+        self.messages.as_ref()
+    }
+
+    /// The decoded message, it is decoded again if its values were evicted by `spill`.
+    pub fn decoded<'a>(&'a self, message: &'a MessageInfo) -> Cow<'a, DecodedMessage> {
+        if !message.evicted {
+            return Cow::Borrowed(&message.decoded);
+        }
+        let chunks = &self.chunks[..message.decoded.chunks.end];
+        match DecodedMessage::decode(self.data(), chunks, message.decoded.chunks.start) {
+            Some(decoded) => Cow::Owned(decoded),
+            None => Cow::Borrowed(&message.decoded),
+        }
+    }
+
+    /// The bodies of the chunks of the message put together.
+    pub fn body(&self, message: &MessageInfo) -> Vec<u8> {
+        self.chunks[message.decoded.chunks.clone()]
+            .iter()
+            .flat_map(|c| self.data()[c.body()].iter().cloned())
+            .collect()
+    }
+
+    /// Whether the buffered data ends exactly at the end of the last chunk,
+    /// so the next payload starts with a new chunk.
+    pub fn aligned(&self) -> bool {
+        self.chunks.last().map(|c| c.range().end).unwrap_or(0) == self.data.len()
+    }
+
     pub fn decrypted(&self) -> usize {
         self.processed
     }
@@ -150,6 +324,26 @@ impl DirectBuffer {
         self.data.as_ref()
     }
 
+    /// Bytes kept in the memory, the data, see `Storage::in_memory`,
+    /// and the decoded values of the messages.
+    pub fn in_memory(&self) -> usize {
+        self.data.in_memory() + self.decoded_size
+    }
+
+    /// Move the data to the temporary file and drop the decoded values to free the memory,
+    /// the values are decoded again from the file when needed.
+    pub fn spill(&mut self) {
+        if let Err(e) = self.data.spill() {
+            log::error!("cannot spill the conversation to the temporary file: {}", e);
+            return;
+        }
+        for message in self.messages.iter_mut().filter(|m| !m.evicted) {
+            message.decoded.values = Vec::new();
+            message.evicted = true;
+        }
+        self.decoded_size = 0;
+    }
+
     pub fn data_mut(&mut self) -> &mut [u8] {
         self.data.as_mut()
     }
@@ -158,7 +352,75 @@ impl DirectBuffer {
         self.chunks.as_ref()
     }
 
-    pub fn packet(&self, index: u64) -> Range<usize> {
-        self.packets.get(&index).unwrap().clone()
+    /// `None` if the payload was not buffered,
+    /// the `offset` is where the payload starts in its data source.
+    pub fn packet(&self, index: u64, offset: usize) -> Option<Range<usize>> {
+        self.packets.get(&(index, offset)).map(|p| p.range.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Desegment, DirectBuffer, MessageInfo, Timestamp};
+    use crate::value::DecodedMessage;
+
+    #[test]
+    fn desegment() {
+        // complete chunks
+        assert_eq!(Desegment::new(&[0, 1, 0xaa, 0, 0]), None);
+        // the second chunk lacks two bytes
+        let request = Desegment {
+            offset: 3,
+            length: Some(2),
+        };
+        assert_eq!(Desegment::new(&[0, 1, 0xaa, 0, 3, 0xbb]), Some(request));
+        // the length of the second chunk is incomplete
+        let request = Desegment {
+            offset: 3,
+            length: None,
+        };
+        assert_eq!(Desegment::new(&[0, 1, 0xaa, 0]), Some(request));
+    }
+
+    #[test]
+    fn several_payloads_in_frame() {
+        let mut buffer = DirectBuffer::new();
+        // the reassembled data and the rest of the segment, both in the frame 5
+        let time = Timestamp::default();
+        buffer.consume(&[0, 1, 0xaa], 5, 0, time);
+        buffer.consume(&[0, 1, 0xbb], 5, 66, time);
+        // the same payload again, by the heuristic
+        buffer.consume(&[0, 1, 0xbb], 5, 66, time);
+        assert_eq!(buffer.data(), &[0, 1, 0xaa, 0, 1, 0xbb]);
+        assert_eq!(buffer.chunks().len(), 2);
+        assert_eq!(buffer.packet(5, 0), Some(0..3));
+        assert_eq!(buffer.packet(5, 66), Some(3..6));
+    }
+
+    #[test]
+    fn spill_evicts_values() {
+        let mut buffer = DirectBuffer::new();
+        // the plain chunk and the metadata message, already decrypted, with the MAC
+        let mut payload = vec![0, 2, 0, 0, 0, 18, 0, 0];
+        payload.extend_from_slice(&[0; 16]);
+        buffer.consume(&payload, 1, 0, Timestamp::default());
+        let decoded = DecodedMessage::decode(buffer.data(), buffer.chunks(), 1).unwrap();
+        buffer.decoded_size += decoded.in_memory();
+        buffer.messages.push(MessageInfo {
+            decoded,
+            frames: vec![1],
+            time: Timestamp::default(),
+            size: 2,
+            evicted: false,
+        });
+        let values = buffer.messages()[0].decoded.values.clone();
+        assert!(!values.is_empty());
+        assert!(buffer.in_memory() > buffer.data.in_memory());
+
+        buffer.spill();
+        assert_eq!(buffer.in_memory(), 0);
+        let message = &buffer.messages()[0];
+        assert!(message.decoded.values.is_empty());
+        assert_eq!(buffer.decoded(message).values, values);
     }
 }
@@ -0,0 +1,169 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::fmt;
+use super::{addresses::Sender, direct_buffer::MessageInfo};
+use crate::value::{DecodedValue, Value, is_variant};
+
+/// The third message of each side, it decides whether the conversation continues.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Ack {
+    Accepted,
+    Rejected,
+    RejectedWithMotive(u16),
+    Unknown(u16),
+}
+
+impl Ack {
+    /// Read the variant of the decoded ack message.
+    pub fn extract(values: &[DecodedValue]) -> Option<Self> {
+        // the first tag is the variant of the ack, the second is the motive of the nack
+        let mut tags = Vec::new();
+        collect(values, &mut tags);
+        let (id, variant) = *tags.first()?;
+        let ack = match variant {
+            Some(variant) if is_variant(variant, "ack") => Ack::Accepted,
+            Some(variant) if is_variant(variant, "nack") => match tags.get(1) {
+                Some(&(motive, _)) => Ack::RejectedWithMotive(motive),
+                None => Ack::Rejected,
+            },
+            Some(_) => Ack::Rejected,
+            None => Ack::Unknown(id),
+        };
+        Some(ack)
+    }
+
+    pub fn accepted(&self) -> bool {
+        match self {
+            &Ack::Accepted => true,
+            _ => false,
+        }
+    }
+}
+
+fn collect<'a>(values: &'a [DecodedValue], tags: &mut Vec<(u16, Option<&'a str>)>) {
+    for value in values {
+        match &value.value {
+            &Value::Tag { id, ref variant } => {
+                tags.push((id, variant.as_ref().map(String::as_str)))
+            },
+            &Value::Node(ref children)
+            | &Value::List(ref children)
+            | &Value::Recursive(ref children) => collect(children, tags),
+            _ => (),
+        }
+    }
+}
+
+impl fmt::Display for Ack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            &Ack::Accepted => write!(f, "accepted"),
+            &Ack::Rejected => write!(f, "rejected"),
+            &Ack::RejectedWithMotive(motive) => {
+                let motive = match motive {
+                    0 => "no motive",
+                    1 => "too many connections",
+                    2 => "unknown chain name",
+                    3 => "deprecated p2p version",
+                    4 => "deprecated distributed db version",
+                    5 => "already connected",
+                    _ => "unknown motive",
+                };
+                write!(f, "rejected with motive: {}", motive)
+            },
+            &Ack::Unknown(id) => write!(f, "unknown ack 0x{:04x}", id),
+        }
+    }
+}
+
+/// The outcome of the handshake, the ack of each side and the frame where it ends.
+pub struct Handshake {
+    pub initiator: Option<(Ack, u64)>,
+    pub responder: Option<(Ack, u64)>,
+}
+
+impl Handshake {
+    /// The ack is the third message.
+    pub const ACK_INDEX: usize = 2;
+
+    pub fn ack(&self, sender: &Sender) -> Option<(Ack, u64)> {
+        match sender {
+            &Sender::Initiator => self.initiator,
+            &Sender::Responder => self.responder,
+        }
+    }
+
+    pub fn complete(&self) -> bool {
+        self.initiator.is_some() && self.responder.is_some()
+    }
+
+    /// The handshake as it was known in the `frame`, the acks ending later are missing.
+    pub fn until(&self, frame: u64) -> Self {
+        let until = |ack: Option<(Ack, u64)>| ack.filter(|&(_, f)| f <= frame);
+        Handshake {
+            initiator: until(self.initiator),
+            responder: until(self.responder),
+        }
+    }
+
+    /// The peer message is sent in the `frame` before the peer received the ack
+    /// of the other side, or after some side rejected the connection.
+    pub fn violated_by(&self, sender: &Sender, message: &MessageInfo) -> bool {
+        let other = match sender {
+            &Sender::Initiator => self.responder,
+            &Sender::Responder => self.initiator,
+        };
+        match (self.ack(sender), other) {
+            (Some((own, _)), Some((other, frame))) => {
+                !own.accepted() || !other.accepted() || message.first_frame() < frame
+            },
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Handshake {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.initiator, self.responder) {
+            (Some((i, _)), Some((r, _))) if i.accepted() && r.accepted() => write!(f, "accepted"),
+            (Some((i, _)), Some((r, _))) if i.accepted() => write!(f, "responder {}", r),
+            (Some((i, _)), _) if !i.accepted() => write!(f, "initiator {}", i),
+            (_, Some((r, _))) if !r.accepted() => write!(f, "responder {}", r),
+            (None, None) => write!(f, "incomplete, no ack"),
+            (None, _) => write!(f, "incomplete, no ack from initiator"),
+            _ => write!(f, "incomplete, no ack from responder"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ack, Handshake};
+    use crate::value::DecodedMessage;
+    use std::ops::Range;
+
+    fn decode(data: &[u8]) -> Option<Ack> {
+        let chunks: Vec<Range<usize>> = vec![0..0, 0..0, 0..data.len()];
+        let message = DecodedMessage::decode(data, chunks.as_ref(), 2).unwrap();
+        Ack::extract(&message.values)
+    }
+
+    #[test]
+    fn ack() {
+        assert_eq!(decode(&[0x00]), Some(Ack::Accepted));
+        assert_eq!(decode(&[0xff]), Some(Ack::Rejected));
+        let nack = [0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(decode(&nack), Some(Ack::RejectedWithMotive(1)));
+        let handshake = Handshake {
+            initiator: Some((Ack::Accepted, 5)),
+            responder: decode(&nack).map(|a| (a, 6)),
+        };
+        let result = "responder rejected with motive: too many connections";
+        assert_eq!(handshake.to_string(), result);
+        assert_eq!(
+            handshake.until(5).to_string(),
+            "incomplete, no ack from responder"
+        );
+    }
+}
@@ -4,6 +4,10 @@
 mod addresses;
 mod overall_buffer;
 mod direct_buffer;
+mod storage;
+mod handshake;
+mod version;
 
 pub use self::addresses::Sender;
 pub use self::overall_buffer::{Context, ErrorPosition};
+pub use self::direct_buffer::MessageInfo;
@@ -2,21 +2,20 @@
 // SPDX-License-Identifier: MIT
 
 use wireshark_epan_adapter::dissector::{PacketInfo, Tree, TreeLeaf};
-use std::ops::Range;
-use tezos_encoding::encoding::HasEncoding;
-use tezos_messages::p2p::encoding::{
-    ack::AckMessage, metadata::MetadataMessage, peer::PeerMessageResponse,
-    connection::ConnectionMessage,
-};
+use std::{ops::Range, borrow::Cow};
 use failure::Fail;
 use super::{
     addresses::{Addresses, Sender},
-    direct_buffer::{DirectBuffer, DecryptError, ChunkInfo},
+    direct_buffer::{DirectBuffer, DecryptError, ChunkInfo, MessageInfo, Desegment, Timestamp},
+    handshake::{Ack, Handshake},
+    version::{NetworkVersion, Negotiation},
 };
 use crate::{
-    identity::{Decipher, Identity, IdentityError},
-    value::{ChunkedData, ChunkedDataOffset, Named, HasBodyRange},
+    identity::{Decipher, Identity, IdentityError, proof_of_work_difficulty},
+    value::{DecodedMessage, HasBodyRange, Violation, MAX_CHUNK_CONTENT},
     range_tool::intersect,
+    options::Options,
+    stats::Stats,
 };
 
 #[derive(Debug, Eq, PartialEq, Fail)]
@@ -25,9 +24,9 @@ pub enum State {
     Correct,
     #[fail(display = "Have no identity")]
     HaveNoIdentity,
-    #[fail(display = "Identity at: {} is invalid", _0)]
+    #[fail(display = "Identity {} is invalid", _0)]
     IdentityInvalid(String),
-    #[fail(display = "Identity at: {} cannot decrypt this conversation", _0)]
+    #[fail(display = "Identities {} cannot decrypt this conversation", _0)]
     IdentityCannotDecrypt(String),
     #[fail(display = "{}", _0)]
     DecryptError(DecryptError),
@@ -59,16 +58,50 @@ pub struct ConversationBuffer {
     addresses: Addresses,
     incoming: DirectBuffer,
     outgoing: DirectBuffer,
+    limit_exceeded: bool,
 }
 
 impl ConversationBuffer {
-    fn consume(&mut self, payload: &[u8], packet_info: &PacketInfo) {
+    fn consume(&mut self, payload: &[u8], packet_info: &PacketInfo, options: &Options) {
+        let limit = options.max_buffered_bytes;
+        let size = self.incoming.data().len() + self.outgoing.data().len();
+        // once the limit is reached, stop buffering the whole conversation,
+        // because skipping some frame breaks the chunk boundaries
+        if self.limit_exceeded || (limit != 0 && size + payload.len() > limit) {
+            self.limit_exceeded = true;
+            return;
+        }
+        let (frame, offset) = (packet_info.frame_number(), packet_info.payload_offset());
+        let time = Timestamp {
+            absolute: packet_info.abs_ts().as_secs_f64(),
+            relative: packet_info.rel_ts(),
+        };
+        match self.addresses.sender(packet_info) {
+            Sender::Initiator => self.incoming.consume(payload, frame, offset, time),
+            Sender::Responder => self.outgoing.consume(payload, frame, offset, time),
+        }
+        let budget = options.conversation_memory;
+        if budget != 0 && self.in_memory() > budget {
+            self.spill();
+        }
+    }
+
+    fn aligned(&self, packet_info: &PacketInfo) -> bool {
         match self.addresses.sender(packet_info) {
-            Sender::Initiator => self.incoming.consume(payload, packet_info.frame_number()),
-            Sender::Responder => self.outgoing.consume(payload, packet_info.frame_number()),
+            Sender::Initiator => self.incoming.aligned(),
+            Sender::Responder => self.outgoing.aligned(),
         }
     }
 
+    fn in_memory(&self) -> usize {
+        self.incoming.in_memory() + self.outgoing.in_memory()
+    }
+
+    fn spill(&mut self) {
+        self.incoming.spill();
+        self.outgoing.spill();
+    }
+
     fn can_upgrade(&self) -> bool {
         match (
             self.incoming.chunks().first(),
@@ -99,10 +132,11 @@ impl ConversationBuffer {
         }
     }
 
-    fn packet(&self, packet_info: &PacketInfo) -> Range<usize> {
+    fn packet(&self, packet_info: &PacketInfo) -> Option<Range<usize>> {
+        let (frame, offset) = (packet_info.frame_number(), packet_info.payload_offset());
         match self.addresses.sender(packet_info) {
-            Sender::Initiator => self.incoming.packet(packet_info.frame_number()),
-            Sender::Responder => self.outgoing.packet(packet_info.frame_number()),
+            Sender::Initiator => self.incoming.packet(frame, offset),
+            Sender::Responder => self.outgoing.packet(frame, offset),
         }
     }
 
@@ -112,6 +146,64 @@ impl ConversationBuffer {
         Ok(())
     }
 
+    fn decode(&mut self) {
+        self.incoming.decode();
+        self.outgoing.decode();
+    }
+
+    fn messages(&self, sender: &Sender) -> &[MessageInfo] {
+        match sender {
+            &Sender::Initiator => self.incoming.messages(),
+            &Sender::Responder => self.outgoing.messages(),
+        }
+    }
+
+    fn body(&self, sender: &Sender, message: &MessageInfo) -> Vec<u8> {
+        match sender {
+            &Sender::Initiator => self.incoming.body(message),
+            &Sender::Responder => self.outgoing.body(message),
+        }
+    }
+
+    fn decoded<'a>(&'a self, sender: &Sender, message: &'a MessageInfo) -> Cow<'a, DecodedMessage> {
+        match sender {
+            &Sender::Initiator => self.incoming.decoded(message),
+            &Sender::Responder => self.outgoing.decoded(message),
+        }
+    }
+
+    fn handshake(&self) -> Handshake {
+        let ack = |buffer: &DirectBuffer| {
+            let message = buffer.messages().get(Handshake::ACK_INDEX)?;
+            let ack = Ack::extract(&buffer.decoded(message).values)?;
+            Some((ack, message.last_frame()))
+        };
+        Handshake {
+            initiator: ack(&self.incoming),
+            responder: ack(&self.outgoing),
+        }
+    }
+
+    // the negotiation is complete in the frame where the later connection message ends
+    fn negotiation(&self) -> Option<(Negotiation, u64)> {
+        let initiator = self.incoming.messages().first()?;
+        let responder = self.outgoing.messages().first()?;
+        let negotiation = Negotiation::new(
+            &NetworkVersion::extract(&self.incoming.decoded(initiator).values),
+            &NetworkVersion::extract(&self.outgoing.decoded(responder).values),
+        )?;
+        Some((negotiation, initiator.last_frame().max(responder.last_frame())))
+    }
+
+    // the frame where the first message after the handshake ends, of either side
+    fn first_peer_message(&self) -> Option<u64> {
+        [&self.incoming, &self.outgoing]
+            .iter()
+            .filter_map(|buffer| buffer.messages().get(Handshake::ACK_INDEX + 1))
+            .map(MessageInfo::last_frame)
+            .min()
+    }
+
     fn decrypted(&self, packet_info: &PacketInfo) -> usize {
         match self.addresses.sender(packet_info) {
             Sender::Initiator => self.incoming.decrypted(),
@@ -127,6 +219,7 @@ impl Context {
                 addresses: Addresses::new(packet_info),
                 incoming: DirectBuffer::new(),
                 outgoing: DirectBuffer::new(),
+                limit_exceeded: false,
             },
             None,
             State::Correct,
@@ -137,34 +230,51 @@ impl Context {
         &mut self,
         payload: &[u8],
         packet_info: &PacketInfo,
-        identity: Option<&(Identity, String)>,
+        identities: &[(Identity, String)],
+        options: &Options,
     ) {
+        if let &mut Context::Regular(ref mut buffer, ..) = self {
+            buffer.consume(payload, packet_info, options);
+        }
+        self.decrypt(identities);
+    }
+
+    fn decrypt(&mut self, identities: &[(Identity, String)]) {
         match self {
             &mut Context::Regular(ref mut buffer, ref mut decipher, ref mut state) => {
-                buffer.consume(payload, packet_info);
                 if decipher.is_none() {
                     let buffer = &*buffer;
                     if buffer.can_upgrade() {
-                        match identity {
-                            Some(&(ref i, ref filename)) => {
-                                let initiator =
-                                    &buffer.incoming.data()[buffer.incoming.chunks()[0].range()];
-                                let responder =
-                                    &buffer.outgoing.data()[buffer.outgoing.chunks()[0].range()];
-                                *decipher = match i.decipher(initiator, responder) {
-                                    Ok(decipher) => Some(decipher),
-                                    Err(IdentityError::Invalid) => {
-                                        *state = State::IdentityInvalid(filename.clone());
-                                        None
-                                    },
-                                    Err(IdentityError::CannotDecrypt) => {
-                                        *state = State::IdentityCannotDecrypt(filename.clone());
-                                        None
+                        if identities.is_empty() {
+                            *state = State::HaveNoIdentity;
+                        } else {
+                            let initiator =
+                                &buffer.incoming.data()[buffer.incoming.chunks()[0].range()];
+                            let responder =
+                                &buffer.outgoing.data()[buffer.outgoing.chunks()[0].range()];
+                            // try each identity, the first that belongs to one of the parties wins
+                            let mut invalid = None;
+                            for &(ref i, ref label) in identities {
+                                match i.decipher(initiator, responder) {
+                                    Ok(d) => {
+                                        *decipher = Some(d);
+                                        break;
                                     },
+                                    Err(IdentityError::Invalid) => invalid = Some(label.clone()),
+                                    Err(IdentityError::CannotDecrypt) => (),
                                 }
-                            },
-                            None => {
-                                *state = State::HaveNoIdentity;
+                            }
+                            if decipher.is_none() {
+                                *state = match invalid {
+                                    Some(label) => State::IdentityInvalid(label),
+                                    None => {
+                                        let labels = identities
+                                            .iter()
+                                            .map(|&(_, ref label)| label.as_str())
+                                            .collect::<Vec<_>>();
+                                        State::IdentityCannotDecrypt(labels.join(", "))
+                                    },
+                                };
                             }
                         }
                     }
@@ -184,6 +294,84 @@ impl Context {
             },
             &mut Context::Unrecognized => (),
         };
+        // decode newly decrypted messages, it happens only once for each message
+        if let &mut Context::Regular(ref mut buffer, ..) = self {
+            buffer.decode();
+        }
+    }
+
+    /// If the frame ends with an incomplete chunk, ask TCP to deliver the chunk
+    /// in the following frame, so each frame contains only complete chunks.
+    /// Returns how many bytes of the payload belong to this frame.
+    pub fn desegment(&self, payload: &[u8], packet_info: &PacketInfo) -> usize {
+        // only for the recognized conversation, otherwise the heuristic
+        // would claim any TCP conversation
+        let buffer = match self {
+            &Context::Regular(ref buffer, Some(_), _) if !buffer.limit_exceeded => buffer,
+            _ => return payload.len(),
+        };
+        // the frame is consumed already, it was aligned if it was cut
+        let aligned = match buffer.packet(packet_info) {
+            Some(range) => range.len() < payload.len(),
+            None => buffer.aligned(packet_info),
+        };
+        match Desegment::new(payload) {
+            Some(Desegment { offset, length }) if aligned => {
+                packet_info.desegment(offset, length);
+                offset
+            },
+            _ => payload.len(),
+        }
+    }
+
+    /// Bytes of the conversation kept in the memory.
+    pub fn in_memory(&self) -> usize {
+        match self {
+            &Context::Regular(ref buffer, ..) => buffer.in_memory(),
+            &Context::Unrecognized => 0,
+        }
+    }
+
+    /// Move the data of the conversation to the temporary file.
+    pub fn spill(&mut self) {
+        if let &mut Context::Regular(ref mut buffer, ..) = self {
+            buffer.spill();
+        }
+    }
+
+    /// Decoded messages sent by the sender.
+    pub fn messages(&self, sender: &Sender) -> &[MessageInfo] {
+        match self {
+            &Context::Regular(ref buffer, ..) => buffer.messages(sender),
+            &Context::Unrecognized => &[],
+        }
+    }
+
+    /// The message without chunk headers and MACs.
+    pub fn body(&self, sender: &Sender, message: &MessageInfo) -> Vec<u8> {
+        match self {
+            &Context::Regular(ref buffer, ..) => buffer.body(sender, message),
+            &Context::Unrecognized => Vec::new(),
+        }
+    }
+
+    /// The decoded message, see `DirectBuffer::decoded`.
+    pub fn decoded<'a>(
+        &'a self,
+        sender: &Sender,
+        message: &'a MessageInfo,
+    ) -> Cow<'a, DecodedMessage> {
+        match self {
+            &Context::Regular(ref buffer, ..) => buffer.decoded(sender, message),
+            &Context::Unrecognized => Cow::Borrowed(&message.decoded),
+        }
+    }
+
+    pub fn address(&self, sender: &Sender) -> Option<String> {
+        match self {
+            &Context::Regular(ref buffer, ..) => Some(buffer.addresses.address(sender).to_string()),
+            &Context::Unrecognized => None,
+        }
     }
 
     pub fn invalid(&self) -> bool {
@@ -229,6 +417,8 @@ impl Context {
         packet_length: usize,
         packet_info: &PacketInfo,
         root: &mut Tree,
+        options: &Options,
+        stats: &Stats,
     ) -> Result<(), ErrorPosition> {
         let mut node = root
             .add("tezos", 0..packet_length, TreeLeaf::nothing())
@@ -242,13 +432,43 @@ impl Context {
         let state = self.state();
         let buffer = self.buffer();
 
-        let direction = match buffer.addresses.sender(packet_info) {
-            Sender::Initiator => "local",
-            Sender::Responder => "remote",
-        };
+        let direction = buffer.addresses.sender(packet_info).direction();
         node.add("source", 0..0, TreeLeaf::Display(direction));
 
-        let space = &buffer.packet(packet_info);
+        if let Some((negotiation, frame)) = buffer.negotiation() {
+            let version = match &negotiation.version {
+                &Some(ref version) => version.to_string(),
+                &None => "none".to_owned(),
+            };
+            node.add("version", 0..0, TreeLeaf::Display(version));
+            if let &Some(ref mismatch) = &negotiation.mismatch {
+                node.add("version.mismatch", 0..0, TreeLeaf::Display(mismatch));
+                if frame == packet_info.frame_number() {
+                    node.expert("tezos.version.incompatible", 0..0, mismatch);
+                }
+            }
+        }
+
+        // the handshake is known only if the conversation is decrypted
+        let handshake = match self {
+            &Context::Regular(_, Some(_), _) => Some(buffer.handshake()),
+            _ => None,
+        };
+        if let &Some(ref handshake) = &handshake {
+            Self::show_handshake(handshake, buffer, packet_info, &mut node);
+        }
+
+        stats.show_frame(packet_info.frame_number(), &mut node);
+
+        let space = match buffer.packet(packet_info) {
+            Some(space) => space,
+            None => {
+                let message = "the frame is not buffered, see `max_buffered_bytes` preference";
+                node.add("buffer_limit", 0..0, TreeLeaf::Display(message));
+                return Ok(());
+            },
+        };
+        let space = &space;
         let data = buffer.data(packet_info);
         let decrypted = buffer.decrypted(packet_info);
         let chunks = buffer.chunks(packet_info);
@@ -270,12 +490,38 @@ impl Context {
 
                     let length = range.len() as i64 - 2;
                     let item = intersect(space, range.start..(range.start + 2));
-                    chunk_node.add("length", item, TreeLeaf::dec(length));
+                    chunk_node.add("length", item.clone(), TreeLeaf::dec(length));
+                    // the first chunk is plain, the others end with the MAC
+                    let mac = if index == 0 { 0 } else { 16 };
+                    let content = (range.len() - 2).saturating_sub(mac);
+                    if content > MAX_CHUNK_CONTENT {
+                        let violation = Violation::chunk_size(range.clone(), content);
+                        chunk_node.expert(violation.kind.expert(), item, violation);
+                    }
 
                     if data.len() >= range.end {
                         let body_range = chunk_info.body();
 
-                        if decrypted > index {
+                        if index == 0 && options.verify_pow {
+                            let pow_start = usize::min(range.end, range.start + 36);
+                            let pow_end = usize::min(range.end, range.start + 60);
+                            let item = intersect(space, pow_start..pow_end);
+                            let leaf = match proof_of_work_difficulty(&data[range.clone()]) {
+                                Some(d) if d >= options.pow_target => {
+                                    format!("valid, difficulty {} >= {}", d, options.pow_target)
+                                },
+                                Some(d) => {
+                                    format!("invalid, difficulty {} < {}", d, options.pow_target)
+                                },
+                                None => "connection message is too short".to_owned(),
+                            };
+                            chunk_node.add("proof_of_work", item, TreeLeaf::Display(leaf));
+                        }
+
+                        if decrypted <= index {
+                            let item = intersect(space, body_range.clone());
+                            chunk_node.add("buffering", item, TreeLeaf::Display("..."));
+                        } else if options.show_chunk_hex {
                             data[body_range.clone()].chunks(0x10).enumerate().for_each(
                                 |(i, line)| {
                                     let start = body_range.start + i * 0x10;
@@ -287,9 +533,6 @@ impl Context {
                                     chunk_node.add("data", item, TreeLeaf::Display(body_hex));
                                 },
                             )
-                        } else {
-                            let item = intersect(space, body_range.clone());
-                            chunk_node.add("buffering", item, TreeLeaf::Display("..."));
                         }
 
                         if index > 0 {
@@ -303,82 +546,139 @@ impl Context {
             }
         }
 
-        let chunks = &chunks[..decrypted];
+        if !options.decode_protocol_data {
+            return Ok(());
+        }
 
-        // first chunk which intersect with the frame
-        // but it might be a continuation of previous message,
-        let find_result = chunks
+        // the messages are decoded once in `consume`, just show those intersecting the frame
+        let sender = buffer.addresses.sender(packet_info);
+        let messages = buffer.messages(&sender);
+        let frame_number = packet_info.frame_number();
+        messages
             .iter()
             .enumerate()
-            .find(|&(_, info)| info.body().end > space.start)
-            .map(|(i, info)| (i, info.continuation()));
-
-        // find the chunk that is not a continuation
-        let first_chunk = find_result.map(|(first_chunk, continuation)| {
-            if continuation {
-                // safe to subtract 1 because the first chunk cannot be a continuation
-                chunks[0..(first_chunk - 1)]
-                    .iter()
-                    .enumerate()
-                    .rev()
-                    .find(|&(_, info)| !info.continuation())
-                    .unwrap()
-                    .0
-            } else {
-                first_chunk
-            }
-        });
-
-        if let Some(first_chunk) = first_chunk {
-            let data = ChunkedData::new(data, chunks);
-            let mut offset = ChunkedDataOffset {
-                chunks_offset: first_chunk,
-                data_offset: chunks[first_chunk].body().start,
-            };
-            loop {
-                if state.error(offset.chunks_offset) {
-                    offset.chunks_offset += 1;
-                    continue;
-                }
-                let on = chunks
-                    .get(offset.chunks_offset)
-                    .map(|c| c.body().start < space.end)
-                    .unwrap_or(false);
-                if !on {
-                    break;
+            .skip_while(|&(_, message)| message.decoded.range.end <= space.start)
+            .take_while(|&(_, message)| message.decoded.range.start < space.end)
+            .for_each(|(index, message)| {
+                Self::show_framing(index, message, chunks, frame_number, space, &mut node);
+                if let &Some(ref handshake) = &handshake {
+                    if index > Handshake::ACK_INDEX
+                        && message.first_frame() == frame_number
+                        && handshake.violated_by(&sender, message)
+                    {
+                        let text = format!("peer message #{} is sent before the ack", index);
+                        node.expert("tezos.handshake.message_before_ack", 0..0, text);
+                    }
                 }
-                offset.data_offset = chunks[offset.chunks_offset].body().start;
-                let (encoding, base) = match offset.chunks_offset {
-                    0 => (ConnectionMessage::encoding(), ConnectionMessage::NAME),
-                    1 => (MetadataMessage::encoding(), MetadataMessage::NAME),
-                    2 => (AckMessage::encoding(), AckMessage::NAME),
-                    _ => (PeerMessageResponse::encoding(), PeerMessageResponse::NAME),
-                };
-                let temp = offset.chunks_offset;
-                match data.show(&mut offset, &encoding, space, base, &mut node) {
-                    Ok(()) => (),
-                    Err(e) => {
-                        let leaf = TreeLeaf::Display(e);
-                        node.add("decoding_error", 0..0, leaf);
-                        break;
-                    },
+                let last = message.last_frame() == frame_number;
+                let decoded = buffer.decoded(&sender, message);
+                if !options.reassemble_messages {
+                    decoded.show(space, &mut node, false, &options.names);
+                } else if last {
+                    decoded.show(space, &mut node, true, &options.names);
+                } else {
+                    let last_frame = TreeLeaf::frame(message.last_frame());
+                    node.add("message.continues_in", 0..0, last_frame);
                 }
-                if offset.data_offset == chunks[offset.chunks_offset].body().end {
-                    offset.chunks_offset += 1;
+                if last {
+                    stats.show(&decoded, &mut node);
+                    for violation in &message.decoded.violations {
+                        let item = intersect(space, violation.range.clone());
+                        node.expert(violation.kind.expert(), item, violation);
+                    }
                 }
-                if offset.chunks_offset == temp {
-                    offset.chunks_offset += 1;
-                    log::warn!(
-                        "ChunkedData::show did not consume full chunk, frame: {}",
-                        packet_info.frame_number()
-                    );
+            });
+
+        // the chunks after the last decoded message belong to the message which is not complete
+        let decoded = messages.last().map(|m| m.decoded.chunks.end).unwrap_or(0);
+        let pending = chunks.get(decoded..).unwrap_or(&[]);
+        if let Some(first) = pending.first() {
+            let range = first.range().start..pending[pending.len() - 1].range().end;
+            if range.start < space.end && range.end > space.start {
+                let item = intersect(space, range);
+                let text = format!("#{}, incomplete, chunks {}..", messages.len(), decoded);
+                let mut message_node = node
+                    .add("message", item, TreeLeaf::Display(text))
+                    .subtree();
+                message_node.add("index", 0..0, TreeLeaf::dec(messages.len() as _));
+                message_node.add("complete", 0..0, TreeLeaf::bool(false));
+                // the frame is visited once the capture is read, no more chunks will come,
+                // decode the received chunks to tell why the message is not complete
+                let received = usize::min(decrypted, chunks.len());
+                let complete = chunks[..received]
+                    .last()
+                    .map(|c| c.range().end <= data.len())
+                    .unwrap_or(false);
+                if packet_info.visited() && decoded < received && complete {
+                    let chunks = &chunks[..received];
+                    let message = DecodedMessage::decode_final(data, chunks, decoded);
+                    if let &Some(ref e) = &message.error {
+                        let item = e.range().map(|r| intersect(space, r)).unwrap_or(0..0);
+                        node.add("decoding_error", item, TreeLeaf::Display(e));
+                    }
                 }
-                chunks[(temp + 1)..offset.chunks_offset]
-                    .iter()
-                    .for_each(ChunkInfo::set_continuation);
             }
         }
 
         Ok(())
     }
+
+    fn show_handshake(
+        handshake: &Handshake,
+        buffer: &ConversationBuffer,
+        packet_info: &PacketInfo,
+        node: &mut Tree,
+    ) {
+        let frame_number = packet_info.frame_number();
+        node.add("handshake.result", 0..0, TreeLeaf::Display(handshake));
+        let rejected = [&handshake.initiator, &handshake.responder]
+            .iter()
+            .filter_map(|ack| ack.as_ref())
+            .any(|&(ack, frame)| !ack.accepted() && frame == frame_number);
+        if rejected {
+            node.expert("tezos.handshake.rejected", 0..0, handshake);
+        } else if buffer.first_peer_message() == Some(frame_number) {
+            // report it once, in the frame where the first peer message ends,
+            // the acks ending later are unknown at this point in the first pass
+            let handshake = handshake.until(frame_number);
+            if !handshake.complete() {
+                node.expert("tezos.handshake.incomplete", 0..0, handshake);
+            }
+        }
+    }
+
+    fn show_framing(
+        index: usize,
+        message: &MessageInfo,
+        chunks: &[ChunkInfo],
+        frame_number: u64,
+        space: &Range<usize>,
+        node: &mut Tree,
+    ) {
+        let first_chunk = message.decoded.chunks.start;
+        let last_chunk = message.decoded.chunks.end - 1;
+        let range = chunks[first_chunk].range().start..chunks[last_chunk].range().end;
+        let text = format!(
+            "#{}, chunks {}..{}, frames {}..{}, {} bytes",
+            index,
+            first_chunk,
+            last_chunk,
+            message.first_frame(),
+            message.last_frame(),
+            message.size,
+        );
+        let mut message_node = node
+            .add("message", intersect(space, range), TreeLeaf::Display(text))
+            .subtree();
+        message_node.add("index", 0..0, TreeLeaf::dec(index as _));
+        message_node.add("size", 0..0, TreeLeaf::dec(message.size as _));
+        message_node.add("first_frame", 0..0, TreeLeaf::frame(message.first_frame()));
+        message_node.add("last_frame", 0..0, TreeLeaf::frame(message.last_frame()));
+        if let Some(part) = message.frames.iter().position(|&f| f == frame_number) {
+            let text = format!("this frame contains part {} of {}", part + 1, message.frames.len());
+            message_node.add("part", 0..0, TreeLeaf::Display(text));
+        }
+        let complete = message.last_frame() == frame_number;
+        message_node.add("complete", 0..0, TreeLeaf::bool(complete));
+    }
 }
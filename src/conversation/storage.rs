@@ -0,0 +1,316 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{
+    fs::File,
+    io,
+    cell::{Cell, RefCell},
+    rc::{Rc, Weak},
+    ops::Range,
+};
+use memmap::{MmapMut, MmapOptions};
+
+/// The temporary file shared by all spilled storages, so only one file descriptor is open
+/// however many conversations are spilled. Each storage maps its own region of the file,
+/// the region freed by the storage which moves or is dropped is reused by the next one.
+/// The file is deleted when the last storage using it is dropped.
+pub struct SpillFile {
+    file: File,
+    // the end of the last region
+    end: Cell<u64>,
+    // freed regions, sorted and never adjacent
+    free: RefCell<Vec<Range<u64>>>,
+}
+
+impl SpillFile {
+    // the region starts at the multiple of the page size on any platform
+    const ALIGN: usize = 0x10000;
+
+    /// The file in use, or a new one if all storages using the previous one are dropped.
+    pub fn shared() -> io::Result<Rc<Self>> {
+        thread_local! {
+            static SHARED: RefCell<Weak<SpillFile>> = RefCell::new(Weak::new());
+        }
+
+        SHARED.with(|shared| {
+            if let Some(file) = shared.borrow().upgrade() {
+                return Ok(file);
+            }
+            let file = Rc::new(SpillFile {
+                file: tempfile::tempfile()?,
+                end: Cell::new(0),
+                free: RefCell::new(Vec::new()),
+            });
+            *shared.borrow_mut() = Rc::downgrade(&file);
+            Ok(file)
+        })
+    }
+
+    /// Bytes of the file, including the freed regions which are not reused yet.
+    pub fn size(&self) -> u64 {
+        self.end.get()
+    }
+
+    // take the first free region large enough, or append the new one at the end
+    fn allocate(&self, length: u64) -> io::Result<u64> {
+        let mut free = self.free.borrow_mut();
+        if let Some(index) = free.iter().position(|r| r.end - r.start >= length) {
+            let offset = free[index].start;
+            free[index].start += length;
+            if free[index].start == free[index].end {
+                free.remove(index);
+            }
+            return Ok(offset);
+        }
+        let offset = self.end.get();
+        self.file.set_len(offset + length)?;
+        self.end.set(offset + length);
+        Ok(offset)
+    }
+
+    fn release(&self, region: Range<u64>) {
+        let mut free = self.free.borrow_mut();
+        let index = free
+            .iter()
+            .position(|r| r.start > region.start)
+            .unwrap_or(free.len());
+        free.insert(index, region);
+        // merge with the neighbours
+        if index + 1 < free.len() && free[index].end == free[index + 1].start {
+            free[index].end = free.remove(index + 1).end;
+        }
+        if index > 0 && free[index - 1].end == free[index].start {
+            free[index - 1].end = free.remove(index).end;
+        }
+        // the free region at the end is given back to the file system
+        if free
+            .last()
+            .map(|r| r.end == self.end.get())
+            .unwrap_or(false)
+        {
+            let last = free.pop().expect("checked above");
+            if let Err(e) = self.file.set_len(last.start) {
+                log::warn!("cannot shrink the temporary file: {}", e);
+                free.push(last);
+            } else {
+                self.end.set(last.start);
+            }
+        }
+    }
+}
+
+// the mapped region of the spill file, it is freed when dropped
+struct Region {
+    map: MmapMut,
+    file: Rc<SpillFile>,
+    offset: u64,
+}
+
+impl Region {
+    // map the region of at least `length` bytes
+    fn new(file: Rc<SpillFile>, length: usize) -> io::Result<Self> {
+        let length = (length + SpillFile::ALIGN - 1) / SpillFile::ALIGN * SpillFile::ALIGN;
+        let offset = file.allocate(length as u64)?;
+        let map = unsafe {
+            MmapOptions::new()
+                .offset(offset)
+                .len(length)
+                .map_mut(&file.file)
+        };
+        match map {
+            Ok(map) => Ok(Region { map, file, offset }),
+            Err(e) => {
+                file.release(offset..(offset + length as u64));
+                Err(e)
+            },
+        }
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        let length = self.map.len() as u64;
+        self.file.release(self.offset..(self.offset + length));
+    }
+}
+
+/// Contiguous growing byte buffer, it is in memory at first,
+/// and might be spilled to the region of the shared temporary memory mapped file.
+pub struct Storage {
+    inner: Inner,
+}
+
+enum Inner {
+    Memory(Vec<u8>),
+    Mapped { region: Region, length: usize },
+}
+
+impl Storage {
+    const MIN_FILE_SIZE: usize = SpillFile::ALIGN;
+
+    pub fn new() -> Self {
+        Storage {
+            inner: Inner::Memory(Vec::new()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.inner {
+            &Inner::Memory(ref data) => data.len(),
+            &Inner::Mapped { length, .. } => length,
+        }
+    }
+
+    /// How many bytes the storage holds in the memory,
+    /// the mapped file is backed by the disk, so it does not count.
+    pub fn in_memory(&self) -> usize {
+        match &self.inner {
+            &Inner::Memory(ref data) => data.capacity(),
+            &Inner::Mapped { .. } => 0,
+        }
+    }
+
+    pub fn spilled(&self) -> bool {
+        match &self.inner {
+            &Inner::Memory(_) => false,
+            &Inner::Mapped { .. } => true,
+        }
+    }
+
+    /// Move the data in the shared temporary file, all further data goes there too.
+    pub fn spill(&mut self) -> io::Result<()> {
+        if let &Inner::Memory(ref data) = &self.inner {
+            let file = SpillFile::shared()?;
+            let length = usize::max(data.len() * 2, Self::MIN_FILE_SIZE);
+            let mut region = Region::new(file, length)?;
+            region.map[..data.len()].clone_from_slice(data.as_ref());
+            self.inner = Inner::Mapped {
+                region,
+                length: data.len(),
+            };
+        }
+        Ok(())
+    }
+
+    pub fn extend(&mut self, payload: &[u8]) {
+        if let Err(e) = self.try_extend(payload) {
+            // the data should not be lost, otherwise the chunks are misaligned,
+            // so move it back in the memory
+            log::error!("cannot extend the temporary file: {}, keep in memory", e);
+            let mut data = self.as_ref().to_vec();
+            data.extend_from_slice(payload);
+            self.inner = Inner::Memory(data);
+        }
+    }
+
+    fn try_extend(&mut self, payload: &[u8]) -> io::Result<()> {
+        match &mut self.inner {
+            &mut Inner::Memory(ref mut data) => data.extend_from_slice(payload),
+            &mut Inner::Mapped {
+                ref mut region,
+                ref mut length,
+            } => {
+                let end = *length + payload.len();
+                if end > region.map.len() {
+                    // the old region is freed when replaced
+                    let size = usize::max(end, region.map.len() * 2);
+                    let mut new = Region::new(region.file.clone(), size)?;
+                    new.map[..*length].clone_from_slice(&region.map[..*length]);
+                    *region = new;
+                }
+                region.map[*length..end].clone_from_slice(payload);
+                *length = end;
+            },
+        }
+        Ok(())
+    }
+}
+
+impl AsRef<[u8]> for Storage {
+    fn as_ref(&self) -> &[u8] {
+        match &self.inner {
+            &Inner::Memory(ref data) => data.as_ref(),
+            &Inner::Mapped { ref region, length } => &region.map[..length],
+        }
+    }
+}
+
+impl AsMut<[u8]> for Storage {
+    fn as_mut(&mut self) -> &mut [u8] {
+        match &mut self.inner {
+            &mut Inner::Memory(ref mut data) => data.as_mut(),
+            &mut Inner::Mapped {
+                ref mut region,
+                length,
+            } => &mut region.map[..length],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use super::{Storage, SpillFile};
+
+    #[test]
+    fn spill_keeps_data() {
+        let mut storage = Storage::new();
+        storage.extend(&[1, 2, 3]);
+        assert!(storage.in_memory() >= 3);
+        storage.spill().unwrap();
+        assert!(storage.spilled());
+        assert_eq!(storage.in_memory(), 0);
+        // grow beyond the initial file size
+        let big = vec![0xaa; Storage::MIN_FILE_SIZE * 3];
+        storage.extend(&big);
+        storage.as_mut()[0] = 4;
+        assert_eq!(storage.len(), 3 + big.len());
+        assert_eq!(&storage.as_ref()[..4], &[4, 2, 3, 0xaa]);
+        assert_eq!(storage.as_ref()[storage.len() - 1], 0xaa);
+    }
+
+    #[test]
+    fn spill_shares_file() {
+        let mut a = Storage::new();
+        let mut b = Storage::new();
+        a.extend(&[1; 10]);
+        b.extend(&[2; 10]);
+        a.spill().unwrap();
+        b.spill().unwrap();
+        // `a` outgrows its region and moves after the region of `b`
+        a.extend(&vec![3; Storage::MIN_FILE_SIZE * 2]);
+        b.extend(&[4]);
+        assert_eq!(&a.as_ref()[8..12], &[1, 1, 3, 3]);
+        assert_eq!(&b.as_ref()[8..], &[2, 2, 4]);
+        let file = SpillFile::shared().unwrap();
+        assert_eq!(Rc::strong_count(&file), 3);
+    }
+
+    #[test]
+    fn spill_reuses_regions() {
+        let mut a = Storage::new();
+        let mut b = Storage::new();
+        a.extend(&[1; 10]);
+        b.extend(&[2; 10]);
+        a.spill().unwrap();
+        b.spill().unwrap();
+        let file = SpillFile::shared().unwrap();
+        let size = Storage::MIN_FILE_SIZE as u64;
+        assert_eq!(file.size(), size * 2);
+        // `a` moves to the end, its first region is free
+        a.extend(&vec![3; Storage::MIN_FILE_SIZE]);
+        assert_eq!(file.size(), size * 4);
+        // `c` takes the freed region instead of growing the file
+        let mut c = Storage::new();
+        c.extend(&[5; 10]);
+        c.spill().unwrap();
+        assert_eq!(file.size(), size * 4);
+        assert_eq!(&c.as_ref()[..], &[5; 10]);
+        // the regions at the end are given back
+        drop(a);
+        assert_eq!(file.size(), size * 2);
+        drop(b);
+        drop(c);
+        assert_eq!(file.size(), 0);
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::fmt;
+use crate::value::{DecodedValue, Value};
+
+/// The version the peer supports, it is in the connection message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NetworkVersion {
+    pub chain_name: String,
+    pub distributed_db_version: i64,
+    pub p2p_version: i64,
+}
+
+impl fmt::Display for NetworkVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, distributed db version {}, p2p version {}",
+            self.chain_name, self.distributed_db_version, self.p2p_version,
+        )
+    }
+}
+
+impl NetworkVersion {
+    /// Find all versions in the decoded connection message.
+    pub fn extract(values: &[DecodedValue]) -> Vec<Self> {
+        let mut versions = Vec::new();
+        collect(values, &mut versions);
+        versions
+    }
+
+    fn key(&self) -> (i64, i64) {
+        (self.distributed_db_version, self.p2p_version)
+    }
+}
+
+fn collect(values: &[DecodedValue], versions: &mut Vec<NetworkVersion>) {
+    let mut chain_name = None;
+    let mut distributed_db_version = None;
+    let mut p2p_version = None;
+    for value in values {
+        match (value.name.as_str(), &value.value) {
+            ("chain_name", &Value::String(ref v)) => chain_name = Some(v.clone()),
+            ("distributed_db_version", &Value::Int(v)) => distributed_db_version = Some(v),
+            ("p2p_version", &Value::Int(v)) => p2p_version = Some(v),
+            (_, &Value::Node(ref children))
+            | (_, &Value::List(ref children))
+            | (_, &Value::Recursive(ref children)) => collect(children, versions),
+            _ => (),
+        }
+    }
+    if let (Some(chain_name), Some(distributed_db_version), Some(p2p_version)) =
+        (chain_name, distributed_db_version, p2p_version)
+    {
+        versions.push(NetworkVersion {
+            chain_name,
+            distributed_db_version,
+            p2p_version,
+        })
+    }
+}
+
+/// The result of the negotiation, both peers use the lowest of their best versions.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Negotiation {
+    pub version: Option<NetworkVersion>,
+    /// Why the peers cannot use the version, if they cannot:
+    /// the chain names differ, or a peer does not support the negotiated version.
+    pub mismatch: Option<String>,
+}
+
+impl Negotiation {
+    pub fn new(initiator: &[NetworkVersion], responder: &[NetworkVersion]) -> Option<Self> {
+        let best = |versions: &[NetworkVersion], chain_name: &str| {
+            versions
+                .iter()
+                .filter(|v| v.chain_name == chain_name)
+                .max_by_key(|v| v.key())
+                .cloned()
+        };
+        let chain_name = initiator
+            .iter()
+            .map(|v| v.chain_name.as_str())
+            .find(|&name| responder.iter().any(|v| v.chain_name == name));
+        let negotiation = match chain_name {
+            Some(chain_name) => {
+                let i = best(initiator, chain_name)?;
+                let r = best(responder, chain_name)?;
+                let version = NetworkVersion {
+                    chain_name: chain_name.to_owned(),
+                    distributed_db_version: i64::min(
+                        i.distributed_db_version,
+                        r.distributed_db_version,
+                    ),
+                    p2p_version: i64::min(i.p2p_version, r.p2p_version),
+                };
+                // negotiating down to the lower version is the normal case
+                let supported = (supports(initiator, &version), supports(responder, &version));
+                let mismatch = match supported {
+                    (true, true) => None,
+                    (false, true) => Some(format!("initiator does not support {}", version)),
+                    (true, false) => Some(format!("responder does not support {}", version)),
+                    (false, false) => Some(format!("neither peer supports {}", version)),
+                };
+                Negotiation {
+                    version: Some(version),
+                    mismatch,
+                }
+            },
+            None => {
+                let (i, r) = (initiator.first()?, responder.first()?);
+                Negotiation {
+                    version: None,
+                    mismatch: Some(format!(
+                        "chain name differs, initiator: {}, responder: {}",
+                        i.chain_name, r.chain_name,
+                    )),
+                }
+            },
+        };
+        Some(negotiation)
+    }
+}
+
+// the single version of the chain means this one and all below it,
+// if the peer lists several, it supports exactly those
+fn supports(versions: &[NetworkVersion], version: &NetworkVersion) -> bool {
+    let mut listed = versions
+        .iter()
+        .filter(|v| v.chain_name == version.chain_name);
+    match (listed.next(), listed.next()) {
+        (Some(only), None) => {
+            only.distributed_db_version >= version.distributed_db_version
+                && only.p2p_version >= version.p2p_version
+        },
+        _ => versions.contains(version),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NetworkVersion, Negotiation};
+
+    fn version(chain_name: &str, distributed_db_version: i64, p2p_version: i64) -> NetworkVersion {
+        NetworkVersion {
+            chain_name: chain_name.to_owned(),
+            distributed_db_version,
+            p2p_version,
+        }
+    }
+
+    #[test]
+    fn negotiate() {
+        let i = [version("TEZOS_MAINNET", 0, 1)];
+        let r = [version("TEZOS_MAINNET", 0, 0)];
+        let negotiation = Negotiation::new(&i, &r).unwrap();
+        assert_eq!(negotiation.version, Some(version("TEZOS_MAINNET", 0, 0)));
+        assert_eq!(negotiation.mismatch, None);
+
+        // the initiator supports only the listed versions
+        let i = [
+            version("TEZOS_MAINNET", 0, 1),
+            version("TEZOS_MAINNET", 1, 1),
+        ];
+        let negotiation = Negotiation::new(&i, &r).unwrap();
+        assert_eq!(negotiation.version, Some(version("TEZOS_MAINNET", 0, 0)));
+        assert!(negotiation.mismatch.unwrap().starts_with("initiator"));
+
+        let r = [version("TEZOS_CARTHAGENET", 0, 1)];
+        let negotiation = Negotiation::new(&i, &r).unwrap();
+        assert_eq!(negotiation.version, None);
+        assert!(negotiation.mismatch.is_some());
+    }
+}
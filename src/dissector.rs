@@ -2,25 +2,39 @@
 // SPDX-License-Identifier: MIT
 
 use wireshark_epan_adapter::{
-    Dissector,
+    Dissector, Preferences,
     dissector::{DissectorHelper, Tree, PacketInfo},
 };
-use std::collections::BTreeMap;
-use super::{conversation::{Context, ErrorPosition, Sender}, identity::Identity};
+use std::{collections::BTreeMap, mem};
+use super::{
+    conversation::{Context, ErrorPosition, Sender},
+    identity::Identity,
+    options::Options,
+    stats::{Stats, Origin},
+};
 
 pub struct TezosDissector {
-    identity: Option<(Identity, String)>,
+    // Each identity has a label shown in error messages.
+    identities: Vec<(Identity, String)>,
+    options: Options,
     // Each pair of endpoints has its own context.
     // The pair is unordered,
     // so A talk to B is the same conversation as B talks to A.
     // The key is just pointer in memory, so it is invalid when capturing session is closed.
     contexts: BTreeMap<usize, ContextExt>,
+    // Aggregated over all conversations.
+    stats: Stats,
+    // The preferences are changed, so wireshark is going to redissect the file,
+    // the stats are not exported until the file is closed.
+    redissecting: bool,
 }
 
 struct ContextExt {
     inner: Context,
     incoming_frame_result: Result<(), ErrorPosition>,
     outgoing_frame_result: Result<(), ErrorPosition>,
+    // How many messages of the initiator and of the responder are already in the stats.
+    scanned: [usize; 2],
 }
 
 impl ContextExt {
@@ -29,6 +43,7 @@ impl ContextExt {
             inner,
             incoming_frame_result: Ok(()),
             outgoing_frame_result: Ok(()),
+            scanned: [0, 0],
         }
     }
 
@@ -51,16 +66,48 @@ impl ContextExt {
         i_error || o_error || self.inner.invalid()
     }
 
+    /// Put newly decoded messages in the stats.
+    pub fn scan(&mut self, packet_info: &PacketInfo, stats: &mut Stats) {
+        let conversation = match self.inner.id() {
+            Some(id) => id,
+            None => return,
+        };
+        let senders = [Sender::Initiator, Sender::Responder];
+        for (sender, scanned) in senders.iter().zip(self.scanned.iter_mut()) {
+            let messages = self.inner.messages(sender);
+            for message in &messages[*scanned..] {
+                let origin = Origin {
+                    conversation: conversation.clone(),
+                    source: self.inner.address(sender).unwrap_or_default(),
+                    direction: sender.direction().to_owned(),
+                    frame: message.last_frame(),
+                    time: message.time.absolute,
+                    relative_time: message.time.relative,
+                    stream: packet_info.tcp_stream(),
+                    interface: packet_info.interface_id(),
+                };
+                let body = self.inner.body(sender, message);
+                stats.scan(&origin, &self.inner.decoded(sender, message), &body);
+            }
+            *scanned = messages.len();
+        }
+    }
+
     pub fn visualize(
         &mut self,
         packet_length: usize,
         packet_info: &PacketInfo,
         root: &mut Tree,
+        options: &Options,
+        stats: &Stats,
     ) -> usize {
         // the context might become invalid if the conversation is not tezos,
         // or if decryption error occurs
         if !self.invalid(packet_info) {
-            match self.inner.visualize(packet_length, packet_info, root) {
+            match self
+                .inner
+                .visualize(packet_length, packet_info, root, options, stats)
+            {
                 Ok(()) => (),
                 Err(r) => match r.sender {
                     Sender::Initiator => self.incoming_frame_result = Err(r),
@@ -77,27 +124,81 @@ impl ContextExt {
 impl TezosDissector {
     pub fn new() -> Self {
         TezosDissector {
-            identity: None,
+            identities: Vec::new(),
+            options: Options::default(),
             contexts: BTreeMap::new(),
+            stats: Stats::default(),
+            redissecting: false,
+        }
+    }
+
+    // Spill the biggest conversations to the disk until all fit in the memory budget.
+    fn limit_memory(&mut self) {
+        let budget = self.options.total_memory;
+        if budget == 0 {
+            return;
+        }
+        let mut total = self
+            .contexts
+            .values()
+            .map(|c| c.inner.in_memory())
+            .sum::<usize>();
+        while total > budget {
+            let biggest = self
+                .contexts
+                .values_mut()
+                .max_by_key(|c| c.inner.in_memory())
+                .filter(|c| c.inner.in_memory() > 0);
+            match biggest {
+                Some(context) => {
+                    total -= context.inner.in_memory();
+                    context.inner.spill();
+                },
+                None => break,
+            }
         }
     }
 }
 
 impl Dissector for TezosDissector {
-    // This method called by the wireshark when the user choose the identity file.
-    fn prefs_update(&mut self, filenames: Vec<&str>) {
-        if let Some(identity_path) = filenames.first().cloned() {
+    // This method called by the wireshark when the user changes the preferences,
+    // for example, choose the identity file.
+    fn prefs_update(&mut self, prefs: Preferences<'_>) {
+        self.options.update(&prefs);
+
+        // rebuild the identity set from the file and the table
+        let mut identities = Vec::new();
+        if let Some(identity_path) = prefs.string("identity_json_file") {
             if !identity_path.is_empty() {
                 // read the identity from the file
-                self.identity = Identity::from_path(identity_path)
-                    .map_err(|e| {
-                        log::error!("Identity: {}", e);
-                        e
-                    })
-                    .map(|i| (i, identity_path.to_owned()))
-                    .ok();
+                match Identity::from_path(identity_path) {
+                    Ok(i) => identities.push((i, identity_path.to_owned())),
+                    Err(e) => log::error!("Identity: {}", e),
+                }
+            }
+        }
+        let rows = prefs.table("identities").unwrap_or(&[]);
+        for (index, row) in rows.iter().enumerate() {
+            if let &[name, value, enabled] = row.as_slice() {
+                if enabled != "TRUE" || value.is_empty() {
+                    continue;
+                }
+                // the value might be a secret key, do not use it as a label
+                let label = if name.is_empty() {
+                    format!("identity #{}", index)
+                } else {
+                    name.to_owned()
+                };
+                match Identity::from_path_or_secret_key(value) {
+                    Ok(i) => identities.push((i, label)),
+                    Err(e) => log::error!("Identity {}: {}", label, e),
+                }
             }
         }
+        // wireshark cleans up and redissects all frames after the preferences are changed,
+        // so the contexts are created again with the new identities
+        self.identities = identities;
+        self.redissecting = true;
     }
 
     // This method called by the wireshark when a new packet just arrive,
@@ -108,6 +209,8 @@ impl Dissector for TezosDissector {
         root: &mut Tree,
         packet_info: &PacketInfo,
     ) -> usize {
+        // the preferences are read before the file is opened, no redissection follows
+        self.redissecting = false;
         // get the data
         let payload = helper.payload();
         // retrieve or create a new context for the conversation
@@ -116,18 +219,65 @@ impl Dissector for TezosDissector {
             .contexts
             .entry(context_key)
             .or_insert_with(|| ContextExt::new(Context::new(packet_info)));
+        // let TCP reassemble the chunk if it continues in the next frame
+        let full_length = payload.len();
+        let payload = if self.options.desegment && packet_info.can_desegment() {
+            &payload[..context.inner.desegment(payload.as_ref(), packet_info)]
+        } else {
+            &payload[..]
+        };
         if !packet_info.visited() {
-            // consume each packet only once
-            context
-                .inner
-                .consume(payload.as_ref(), packet_info, self.identity.as_ref());
+            // consume each packet only once, the retransmitted data is already consumed
+            if !packet_info.retransmission() {
+                context.inner.consume(
+                    payload,
+                    packet_info,
+                    self.identities.as_ref(),
+                    &self.options,
+                );
+            }
+            context.scan(packet_info, &mut self.stats);
+        }
+        let length =
+            context.visualize(payload.len(), packet_info, root, &self.options, &self.stats);
+        if !packet_info.visited() {
+            self.limit_memory();
+        }
+        // the rest of the frame is delivered again in the next frame
+        if packet_info.desegment_requested() {
+            full_length
+        } else {
+            length
         }
-        context.visualize(payload.len(), packet_info, root)
     }
 
     // This method called by the wireshark when the user
-    // closing current capturing session
+    // closing current capturing session, or before the redissection
     fn cleanup(&mut self) {
+        if mem::replace(&mut self.redissecting, false) {
+            self.contexts.clear();
+            self.stats.clear();
+            return;
+        }
+        let path = &self.options.known_points_file;
+        if !path.is_empty() && !self.contexts.is_empty() {
+            if let Err(e) = self.stats.export_points(path) {
+                log::error!("cannot export known points to {}: {}", path, e);
+            }
+        }
+        let path = &self.options.block_timeline_file;
+        if !path.is_empty() && !self.contexts.is_empty() {
+            if let Err(e) = self.stats.export_blocks(path) {
+                log::error!("cannot export block timeline to {}: {}", path, e);
+            }
+        }
+        let path = &self.options.operations_file;
+        if !path.is_empty() && !self.contexts.is_empty() {
+            if let Err(e) = self.stats.export_operations(path) {
+                log::error!("cannot export operations to {}: {}", path, e);
+            }
+        }
         self.contexts.clear();
+        self.stats.clear();
     }
 }
@@ -39,6 +39,32 @@ impl Identity {
         Ok(identity)
     }
 
+    /// Create the identity from the hex encoded secret key, the public key is derived from it.
+    pub fn from_secret_key(secret_key: &str) -> Result<Self, failure::Error> {
+        use sodiumoxide::crypto::box_::SecretKey;
+
+        let decoded = hex::decode(secret_key)?;
+        let key = SecretKey::from_slice(&decoded)
+            .ok_or_else(|| failure::format_err!("secret key should be 32 bytes"))?;
+        let public_key = key.public_key();
+        Ok(Identity {
+            peer_id: String::new(),
+            public_key: HashType::CryptoboxPublicKeyHash.bytes_to_string(public_key.as_ref()),
+            secret_key: secret_key.to_owned(),
+            proof_of_work_stamp: String::new(),
+        })
+    }
+
+    /// The value is either the hex encoded secret key, or the path to the identity json file.
+    pub fn from_path_or_secret_key(value: &str) -> Result<Self, failure::Error> {
+        let is_key = value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit());
+        if is_key {
+            Identity::from_secret_key(value)
+        } else {
+            Identity::from_path(value)
+        }
+    }
+
     /// Create a decipher object using connection message pair.
     pub fn decipher(
         &self,
@@ -68,6 +94,23 @@ impl Identity {
     }
 }
 
+/// Number of leading zero bits of the hash of the public key and the proof of work stamp.
+/// The chunk is the connection message including 2 bytes of the chunk length.
+pub fn proof_of_work_difficulty(connection_chunk: &[u8]) -> Option<u32> {
+    use crypto::blake2b;
+
+    // public key is at 4..36, proof of work stamp is at 36..60
+    let hash = blake2b::digest_256(connection_chunk.get(4..60)?);
+    let mut difficulty = 0;
+    for byte in hash {
+        difficulty += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+    Some(difficulty)
+}
+
 /// Decipher object, contains precomputed key and initial nonces
 pub struct Decipher {
     key: PrecomputedKey,
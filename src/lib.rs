@@ -9,9 +9,17 @@ mod dissector;
 mod conversation;
 
 mod value;
+// the benchmark of the field registration needs it
+pub use self::value::TezosEncoded;
 
 mod range_tool;
 
 mod identity;
 
+mod names;
+
+mod options;
+
+mod stats;
+
 mod plugin;
@@ -0,0 +1,90 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use std::{collections::BTreeMap, path::Path};
+
+/// Human readable names of chain ids and protocol hashes.
+#[derive(Clone, Debug)]
+pub struct Names {
+    names: BTreeMap<String, String>,
+}
+
+impl Default for Names {
+    fn default() -> Self {
+        let names = Self::WELL_KNOWN
+            .iter()
+            .map(|&(hash, name)| (hash.to_owned(), name.to_owned()))
+            .collect();
+        Names { names }
+    }
+}
+
+impl Names {
+    const WELL_KNOWN: &'static [(&'static str, &'static str)] = &[
+        // chains, the default sandbox starts from the genesis block of mainnet,
+        // so it has the same chain id, a custom sandbox has its own genesis and chain id,
+        // it is named in the json file
+        ("NetXdQprcVkpaWU", "mainnet"),
+        ("NetXjD3HPJJjmcd", "carthagenet"),
+        ("NetXm8tYqnMWky1", "delphinet"),
+        ("NetXSgo1ZT2DRUG", "edo2net"),
+        ("NetXxkAx4woPLyu", "florencenet"),
+        ("NetXz969SFaFn8k", "granadanet"),
+        // protocols
+        ("PtYuensgYBb3G3x1hLLbCmcav8ue8Kyd2khADcL5LsT5R1hcXex", "genesis"),
+        ("PtCJ7pwoxe8JasnHY8YonnLYjcVHmhiARPJvqcC6VfHT5s8k8sY", "001"),
+        ("PsYLVpVvgbLhAhoqAkMFUo6gudkJ9weNXhUYCiLDzcUpFpkk8Wt", "002"),
+        ("PsddFKi32cMJ2qPjf43Qv5GDWLDPZb3T3bF6fLKiF5HtvHNU7aP", "003"),
+        ("Pt24m4xiPbLDhVgVfABUjirbmda3yohdN82Sp1FeuXXhWj5qyCa", "athens"),
+        ("PsBabyM1eUXZseaJdmXFApDSBqj8YBfwELoxZHHW77EMcAbbwAS", "babylon"),
+        ("PsCARTHAGazKbHtnKfLzQg3kms52kSRpgnDY982a9oYsSXRLQEb", "carthage"),
+        ("PsDELPH1Kxsxt8f9eWbxQeRxkjfbxoqM52jvs5Y5fBxWWh4ifpo", "delphi"),
+        ("PtEdo2ZkT9oKpimTah6x2embF25oss54njMuPzkJTEi5RqfdZFA", "edo"),
+        ("PsFLorenaUUuikDWvMDr6fGBRG8kt3e3D3fHoXK1j1BFRxeSH4i", "florence"),
+        ("PtGRANADsDU8R9daYKAgWnQYAJ64omN1o3KMGVCykShA97vQbvV", "granada"),
+    ];
+
+    /// The well known names, extended by the json file,
+    /// the file contains the object where the key is the hash and the value is the name.
+    /// The names from the file take precedence.
+    pub fn from_path<P>(path: P) -> Result<Self, failure::Error>
+    where
+        P: AsRef<Path>,
+    {
+        let content = std::fs::read_to_string(path.as_ref())?;
+        Self::from_json(&content)
+    }
+
+    pub fn from_json(content: &str) -> Result<Self, failure::Error> {
+        let custom: BTreeMap<String, String> = serde_json::from_str(content)?;
+        let mut names = Names::default();
+        names.names.extend(custom);
+        Ok(names)
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&str> {
+        self.names.get(hash).map(String::as_str)
+    }
+
+    /// For example `NetXdQprcVkpaWU (mainnet)`.
+    pub fn display(&self, hash: &str) -> String {
+        match self.get(hash) {
+            Some(name) => format!("{} ({})", hash, name),
+            None => hash.to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Names;
+
+    #[test]
+    fn custom_names() {
+        // the sandbox with a custom genesis block
+        let names = Names::from_json(r#"{"NetXsqzbfFenSTS": "sandbox"}"#).unwrap();
+        assert_eq!(names.display("NetXdQprcVkpaWU"), "NetXdQprcVkpaWU (mainnet)");
+        assert_eq!(names.get("NetXsqzbfFenSTS"), Some("sandbox"));
+        assert_eq!(names.get("NetXunknown"), None);
+    }
+}
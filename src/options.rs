@@ -0,0 +1,106 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use wireshark_epan_adapter::Preferences;
+use crate::names::Names;
+
+/// Options of the dissector, the user can change them in preferences.
+/// See `plugin.rs` for the names and default values.
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub show_chunk_hex: bool,
+    pub verify_pow: bool,
+    pub pow_target: u32,
+    pub decode_protocol_data: bool,
+    /// Show the whole message in the frame where it ends,
+    /// otherwise each frame shows the part of the message it contains.
+    pub reassemble_messages: bool,
+    /// Ask TCP to reassemble chunks spanning multiple segments.
+    pub desegment: bool,
+    /// Zero means unlimited.
+    pub max_buffered_bytes: usize,
+    /// Bytes of the conversation kept in the memory before it spills to the disk,
+    /// zero means unlimited.
+    pub conversation_memory: usize,
+    /// The same, but for all conversations.
+    pub total_memory: usize,
+    /// Write the known points in this file when the capture is closed, empty means do not.
+    pub known_points_file: String,
+    /// Write where and when each block is seen in this file, empty means do not.
+    pub block_timeline_file: String,
+    /// Write where and when each operation is seen in this file, empty means do not.
+    pub operations_file: String,
+    /// Names of chain ids and protocol hashes, the well known and from the file.
+    pub names: Names,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            show_chunk_hex: true,
+            verify_pow: false,
+            pow_target: 26,
+            decode_protocol_data: true,
+            reassemble_messages: true,
+            desegment: true,
+            max_buffered_bytes: 0,
+            conversation_memory: 64 * Self::MIB,
+            total_memory: 1024 * Self::MIB,
+            known_points_file: String::new(),
+            block_timeline_file: String::new(),
+            operations_file: String::new(),
+            names: Names::default(),
+        }
+    }
+}
+
+impl Options {
+    const MIB: usize = 0x100000;
+
+    pub fn update(&mut self, prefs: &Preferences) {
+        let default = Options::default();
+        self.show_chunk_hex = prefs
+            .bool("show_chunk_hex")
+            .unwrap_or(default.show_chunk_hex);
+        self.verify_pow = prefs.bool("verify_pow").unwrap_or(default.verify_pow);
+        self.pow_target = prefs.uint("pow_target").unwrap_or(default.pow_target);
+        self.decode_protocol_data = prefs
+            .bool("decode_protocol_data")
+            .unwrap_or(default.decode_protocol_data);
+        self.reassemble_messages = prefs
+            .bool("reassemble_messages")
+            .unwrap_or(default.reassemble_messages);
+        self.desegment = prefs.bool("desegment").unwrap_or(default.desegment);
+        self.max_buffered_bytes = prefs
+            .uint("max_buffered_bytes")
+            .map(|b| b as usize)
+            .unwrap_or(default.max_buffered_bytes);
+        self.conversation_memory = prefs
+            .uint("conversation_memory_mib")
+            .map(|m| m as usize * Self::MIB)
+            .unwrap_or(default.conversation_memory);
+        self.total_memory = prefs
+            .uint("total_memory_mib")
+            .map(|m| m as usize * Self::MIB)
+            .unwrap_or(default.total_memory);
+        self.known_points_file = prefs
+            .string("known_points_file")
+            .map(ToOwned::to_owned)
+            .unwrap_or(default.known_points_file);
+        self.block_timeline_file = prefs
+            .string("block_timeline_file")
+            .map(ToOwned::to_owned)
+            .unwrap_or(default.block_timeline_file);
+        self.operations_file = prefs
+            .string("operations_file")
+            .map(ToOwned::to_owned)
+            .unwrap_or(default.operations_file);
+        self.names = match prefs.string("names_json_file") {
+            Some(path) if !path.is_empty() => Names::from_path(path).unwrap_or_else(|e| {
+                log::error!("Names: {}", e);
+                default.names
+            }),
+            _ => default.names,
+        };
+    }
+}
@@ -4,7 +4,8 @@
 #[rustfmt::skip]
 use wireshark_epan_adapter::{
     Plugin, NameDescriptor, FieldDescriptor,
-    PrefFilenameDescriptor,
+    PrefDescriptor, TableFieldDescriptor, TableFieldKind,
+    ExpertDescriptor, ExpertGroup, ExpertSeverity,
     DissectorDescriptor,
 };
 use tezos_messages::p2p::encoding::{
@@ -30,6 +31,7 @@ extern "C" fn plugin_register() {
         DissectorDescriptor {
             display_name: "Tezos\0",
             short_name: "tezos_tcp\0",
+            tcp_ports: "9732\0",
         },
         NameDescriptor {
             name: "Tezos Protocol\0",
@@ -55,6 +57,130 @@ extern "C" fn plugin_register() {
                     name: "Decoding error\0",
                     abbrev: "tezos.decoding_error\0",
                 },
+                FieldDescriptor::String {
+                    name: "Buffer limit exceeded\0",
+                    abbrev: "tezos.buffer_limit\0",
+                },
+                FieldDescriptor::String {
+                    name: "Chain name\0",
+                    abbrev: "tezos.chain_name\0",
+                },
+                FieldDescriptor::String {
+                    name: "Known point\0",
+                    abbrev: "tezos.known_point\0",
+                },
+            ],
+            // handshake
+            &[
+                FieldDescriptor::String {
+                    name: "Negotiated version\0",
+                    abbrev: "tezos.version\0",
+                },
+                FieldDescriptor::String {
+                    name: "Version mismatch\0",
+                    abbrev: "tezos.version.mismatch\0",
+                },
+                FieldDescriptor::String {
+                    name: "Handshake result\0",
+                    abbrev: "tezos.handshake.result\0",
+                },
+            ],
+            // block
+            &[
+                FieldDescriptor::String {
+                    name: "Block\0",
+                    abbrev: "tezos.block\0",
+                },
+                FieldDescriptor::String {
+                    name: "Block hash\0",
+                    abbrev: "tezos.block.hash\0",
+                },
+                FieldDescriptor::FrameNumber {
+                    name: "Block first seen in frame\0",
+                    abbrev: "tezos.block.first_seen\0",
+                },
+                FieldDescriptor::Int64Dec {
+                    name: "Milliseconds since the block is seen first\0",
+                    abbrev: "tezos.block.delay\0",
+                },
+                FieldDescriptor::Int64Dec {
+                    name: "Times the block is seen\0",
+                    abbrev: "tezos.block.seen\0",
+                },
+            ],
+            // operation
+            &[
+                FieldDescriptor::String {
+                    name: "Operation\0",
+                    abbrev: "tezos.operation\0",
+                },
+                FieldDescriptor::String {
+                    name: "Operation hash\0",
+                    abbrev: "tezos.operation.hash\0",
+                },
+                FieldDescriptor::FrameNumber {
+                    name: "Operation first seen in frame\0",
+                    abbrev: "tezos.operation.first_seen\0",
+                },
+                FieldDescriptor::Int64Dec {
+                    name: "Milliseconds since the operation is seen first\0",
+                    abbrev: "tezos.operation.delay\0",
+                },
+                FieldDescriptor::Int64Dec {
+                    name: "Peers who sent the operation\0",
+                    abbrev: "tezos.operation.peers\0",
+                },
+                FieldDescriptor::Bool {
+                    name: "Operation is included in a block\0",
+                    abbrev: "tezos.operation.included\0",
+                },
+                FieldDescriptor::String {
+                    name: "Block including the operation\0",
+                    abbrev: "tezos.operation.block\0",
+                },
+                FieldDescriptor::FrameNumber {
+                    name: "Operation included in frame\0",
+                    abbrev: "tezos.operation.included_in\0",
+                },
+                FieldDescriptor::Int64Dec {
+                    name: "Milliseconds since the operation is seen first until included\0",
+                    abbrev: "tezos.operation.inclusion_delay\0",
+                },
+            ],
+            // message
+            &[
+                FieldDescriptor::String {
+                    name: "Message\0",
+                    abbrev: "tezos.message\0",
+                },
+                FieldDescriptor::Int64Dec {
+                    name: "Message index\0",
+                    abbrev: "tezos.message.index\0",
+                },
+                FieldDescriptor::Int64Dec {
+                    name: "Message size\0",
+                    abbrev: "tezos.message.size\0",
+                },
+                FieldDescriptor::FrameNumber {
+                    name: "First frame\0",
+                    abbrev: "tezos.message.first_frame\0",
+                },
+                FieldDescriptor::FrameNumber {
+                    name: "Last frame\0",
+                    abbrev: "tezos.message.last_frame\0",
+                },
+                FieldDescriptor::String {
+                    name: "Part\0",
+                    abbrev: "tezos.message.part\0",
+                },
+                FieldDescriptor::Bool {
+                    name: "Complete in this frame\0",
+                    abbrev: "tezos.message.complete\0",
+                },
+                FieldDescriptor::FrameNumber {
+                    name: "Message continues in frame\0",
+                    abbrev: "tezos.message.continues_in\0",
+                },
             ],
             // chunk
             &[
@@ -79,14 +205,195 @@ extern "C" fn plugin_register() {
                     name: "Message authentication code\0",
                     abbrev: "tezos.chunk.mac\0",
                 },
+                FieldDescriptor::String {
+                    name: "Proof of work\0",
+                    abbrev: "tezos.chunk.proof_of_work\0",
+                },
             ],
         ],
-        &[PrefFilenameDescriptor {
-            name: "identity_json_file\0",
-            title: "Identity JSON file\0",
-            description: "JSON file with node identity information\0",
-        }],
+        // the port preference "tezos.tcp.port" is registered by the adapter
+        &[
+            PrefDescriptor::Filename {
+                name: "identity_json_file\0",
+                title: "Identity JSON file\0",
+                description: "JSON file with node identity information\0",
+            },
+            PrefDescriptor::Table {
+                name: "identities\0",
+                title: "Identities\0",
+                description: "Node identities used to decrypt conversations\0",
+                filename: "tezos_identities\0",
+                fields: &[
+                    TableFieldDescriptor {
+                        name: "name\0",
+                        title: "Name\0",
+                        description: "Name of the identity, shown in error messages\0",
+                        kind: TableFieldKind::String,
+                    },
+                    TableFieldDescriptor {
+                        name: "identity\0",
+                        title: "Identity\0",
+                        description: "Path to the identity JSON file or hex encoded secret key\0",
+                        kind: TableFieldKind::Filename,
+                    },
+                    TableFieldDescriptor {
+                        name: "enabled\0",
+                        title: "Enabled\0",
+                        description: "Use this identity for decryption\0",
+                        kind: TableFieldKind::Bool,
+                    },
+                ],
+            },
+            PrefDescriptor::Bool {
+                name: "show_chunk_hex\0",
+                title: "Show chunk hex dumps\0",
+                description: "Show decrypted data of each chunk as hex\0",
+                default: true,
+            },
+            PrefDescriptor::Bool {
+                name: "verify_pow\0",
+                title: "Verify proof of work\0",
+                description: "Check the proof of work stamp in connection messages\0",
+                default: false,
+            },
+            PrefDescriptor::Uint {
+                name: "pow_target\0",
+                title: "Proof of work target\0",
+                description: "Expected number of leading zero bits of the proof of work\0",
+                default: 26,
+            },
+            PrefDescriptor::Bool {
+                name: "decode_protocol_data\0",
+                title: "Decode protocol data\0",
+                description: "Decode messages, otherwise show only chunks\0",
+                default: true,
+            },
+            PrefDescriptor::Bool {
+                name: "reassemble_messages\0",
+                title: "Show messages in the frame where they end\0",
+                description: "Show the whole decoded message in the frame where it ends, \
+                              otherwise each frame shows its own part of the message\0",
+                default: true,
+            },
+            PrefDescriptor::Bool {
+                name: "desegment\0",
+                title: "Reassemble chunks spanning multiple TCP segments\0",
+                description: "Whether the dissector should reassemble chunks spanning \
+                              multiple TCP segments. To use this option, you must also enable \
+                              \"Allow subdissectors to reassemble TCP streams\" \
+                              in the TCP protocol settings.\0",
+                default: true,
+            },
+            PrefDescriptor::Uint {
+                name: "max_buffered_bytes\0",
+                title: "Max buffered bytes per conversation\0",
+                description: "Stop buffering the conversation after this size, 0 means unlimited\0",
+                default: 0,
+            },
+            PrefDescriptor::Filename {
+                name: "names_json_file\0",
+                title: "Chain and protocol names\0",
+                description: "JSON file with names of chain ids and protocol hashes, \
+                              in addition to the well known ones, for example \
+                              {\"NetXdQprcVkpaWU\": \"mainnet\"}\0",
+            },
+            PrefDescriptor::Filename {
+                name: "known_points_file\0",
+                title: "Export known points\0",
+                description: "When the capture is closed, write the points advertised by peers \
+                              in this file, CSV if the extension is .csv, otherwise JSON\0",
+            },
+            PrefDescriptor::Filename {
+                name: "block_timeline_file\0",
+                title: "Export block timeline\0",
+                description: "When the capture is closed, write where and when each block \
+                              is seen in this file, CSV if the extension is .csv, otherwise JSON\0",
+            },
+            PrefDescriptor::Filename {
+                name: "operations_file\0",
+                title: "Export operations\0",
+                description: "When the capture is closed, write where and when each operation \
+                              is seen and which block includes it in this file, \
+                              CSV if the extension is .csv, otherwise JSON\0",
+            },
+            PrefDescriptor::Uint {
+                name: "conversation_memory_mib\0",
+                title: "Memory per conversation (MiB)\0",
+                description: "Move the data of the conversation to the temporary file \
+                              after this size, 0 means unlimited\0",
+                default: 64,
+            },
+            PrefDescriptor::Uint {
+                name: "total_memory_mib\0",
+                title: "Total memory (MiB)\0",
+                description: "Move the biggest conversations to the temporary file \
+                              when all together exceed this size, 0 means unlimited\0",
+                default: 1024,
+            },
+        ],
     )
+    .register_experts(&[
+        ExpertDescriptor {
+            name: "tezos.version.incompatible\0",
+            summary: "The peers cannot use the negotiated version\0",
+            group: ExpertGroup::Protocol,
+            severity: ExpertSeverity::Warn,
+        },
+        ExpertDescriptor {
+            name: "tezos.handshake.rejected\0",
+            summary: "The connection is rejected\0",
+            group: ExpertGroup::ResponseCode,
+            severity: ExpertSeverity::Note,
+        },
+        ExpertDescriptor {
+            name: "tezos.handshake.incomplete\0",
+            summary: "The handshake is not completed\0",
+            group: ExpertGroup::Sequence,
+            severity: ExpertSeverity::Warn,
+        },
+        ExpertDescriptor {
+            name: "tezos.handshake.message_before_ack\0",
+            summary: "Peer message is sent before the ack\0",
+            group: ExpertGroup::Protocol,
+            severity: ExpertSeverity::Warn,
+        },
+        ExpertDescriptor {
+            name: "tezos.conformance.chunk_size\0",
+            summary: "The chunk content is larger than the protocol allows\0",
+            group: ExpertGroup::Malformed,
+            severity: ExpertSeverity::Warn,
+        },
+        ExpertDescriptor {
+            name: "tezos.conformance.message_size\0",
+            summary: "The message is larger than the Tezos node accepts\0",
+            group: ExpertGroup::Protocol,
+            severity: ExpertSeverity::Warn,
+        },
+        ExpertDescriptor {
+            name: "tezos.conformance.list_length\0",
+            summary: "The list is longer than the Tezos node accepts\0",
+            group: ExpertGroup::Protocol,
+            severity: ExpertSeverity::Warn,
+        },
+        ExpertDescriptor {
+            name: "tezos.conformance.path_depth\0",
+            summary: "The operations path is deeper than possible\0",
+            group: ExpertGroup::Malformed,
+            severity: ExpertSeverity::Warn,
+        },
+        ExpertDescriptor {
+            name: "tezos.conformance.trailing_bytes\0",
+            summary: "Bytes left after the end of the message\0",
+            group: ExpertGroup::Malformed,
+            severity: ExpertSeverity::Warn,
+        },
+        ExpertDescriptor {
+            name: "tezos.conformance.non_canonical\0",
+            summary: "The number is not encoded in the shortest form\0",
+            group: ExpertGroup::Malformed,
+            severity: ExpertSeverity::Warn,
+        },
+    ])
     // declare fields needed for presenting types
     .register_type::<TezosEncoded<ConnectionMessage>>()
     .register_type::<TezosEncoded<MetadataMessage>>()
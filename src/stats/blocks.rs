@@ -0,0 +1,191 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use wireshark_epan_adapter::dissector::{Tree, TreeLeaf};
+use crypto::{hash::HashType, blake2b};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+use super::{Origin, csv_field};
+use crate::value::{DecodedValue, Value, is_variant};
+
+/// The block is seen in the message of some kind.
+#[derive(Clone, Debug, Serialize)]
+pub struct Sighting {
+    /// The message variant: `CurrentHead`, `BlockHeader` or `CurrentBranch`.
+    pub kind: String,
+    #[serde(flatten)]
+    pub origin: Origin,
+}
+
+#[derive(Serialize)]
+struct Block<'a> {
+    block: &'a str,
+    sightings: &'a [Sighting],
+}
+
+/// Where and when each block hash is seen, across all conversations.
+#[derive(Default)]
+pub struct BlockTimeline {
+    blocks: BTreeMap<String, Vec<Sighting>>,
+    // hashes of the blocks seen in the frame
+    frames: BTreeMap<u64, Vec<String>>,
+}
+
+impl BlockTimeline {
+    /// The `body` is the whole message, the ranges of the values are relative to it.
+    pub fn scan(&mut self, origin: &Origin, values: &[DecodedValue], body: &[u8]) {
+        let mut headers = Vec::new();
+        collect(values, None, &mut headers);
+        for (kind, header) in headers {
+            let bytes = match body.get(header.message_range.clone()) {
+                Some(bytes) => bytes,
+                None => continue,
+            };
+            let hash = HashType::BlockHash.bytes_to_string(&blake2b::digest_256(bytes));
+            let frame = self.frames.entry(origin.frame).or_insert_with(Vec::new);
+            if !frame.contains(&hash) {
+                frame.push(hash.clone());
+            }
+            self.blocks.entry(hash).or_insert_with(Vec::new).push(Sighting {
+                kind: kind.to_owned(),
+                origin: origin.clone(),
+            });
+        }
+    }
+
+    /// Show the blocks seen in the frame, and when they are seen first.
+    pub fn show(&self, frame: u64, node: &mut Tree) {
+        for hash in self.frames.get(&frame).into_iter().flatten() {
+            let sightings = &self.blocks[hash];
+            let first = &sightings[0].origin;
+            let this = sightings
+                .iter()
+                .map(|s| &s.origin)
+                .find(|o| o.frame == frame)
+                .unwrap_or(first);
+            let delay = this.delay_since(first);
+            let text = format!("{}, first seen in frame {}, +{} ms", hash, first.frame, delay);
+            let mut block_node = node.add("block", 0..0, TreeLeaf::Display(text)).subtree();
+            block_node.add("hash", 0..0, TreeLeaf::Display(hash));
+            block_node.add("first_seen", 0..0, TreeLeaf::frame(first.frame));
+            block_node.add("delay", 0..0, TreeLeaf::dec(delay));
+            block_node.add("seen", 0..0, TreeLeaf::dec(sightings.len() as _));
+        }
+    }
+
+    pub fn write_csv<W>(&self, w: &mut W) -> io::Result<()>
+    where
+        W: Write + ?Sized,
+    {
+        writeln!(w, "block,kind,direction,source,conversation,frame,time,delay_ms")?;
+        for (block, sightings) in &self.blocks {
+            let first = &sightings[0].origin;
+            for s in sightings {
+                let o = &s.origin;
+                writeln!(
+                    w,
+                    "{},{},{},{},{},{},{:.6},{}",
+                    block,
+                    csv_field(&s.kind),
+                    o.direction,
+                    csv_field(&o.source),
+                    csv_field(&o.conversation),
+                    o.frame,
+                    o.time,
+                    o.delay_since(first),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_json<W>(&self, w: &mut W) -> io::Result<()>
+    where
+        W: Write + ?Sized,
+    {
+        let blocks = self
+            .blocks
+            .iter()
+            .map(|(block, sightings)| Block { block, sightings })
+            .collect::<Vec<_>>();
+        serde_json::to_writer_pretty(w, &blocks).map_err(io::Error::from)
+    }
+}
+
+// the blocks are in these messages
+fn carries_blocks(name: &str) -> bool {
+    ["current_head", "block_header", "current_branch"]
+        .iter()
+        .any(|variant| is_variant(name, variant))
+}
+
+// the block header has these fields
+fn is_header(children: &[DecodedValue]) -> bool {
+    ["level", "predecessor", "operations_hash"]
+        .iter()
+        .all(|name| children.iter().any(|c| c.name == *name))
+}
+
+// find all block headers inside the messages carrying blocks
+fn collect<'a>(
+    values: &'a [DecodedValue],
+    kind: Option<&'a str>,
+    out: &mut Vec<(&'a str, &'a DecodedValue)>,
+) {
+    for value in values {
+        let kind = match kind {
+            Some(kind) => Some(kind),
+            None if carries_blocks(&value.name) => Some(value.name.as_str()),
+            None => None,
+        };
+        match (&value.value, kind) {
+            (&Value::Node(ref children), Some(kind)) if is_header(children) => {
+                out.push((kind, value))
+            },
+            _ => collect(value.children(), kind, out),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockTimeline, Origin};
+    use crate::value::{DecodedValue, Value};
+
+    #[test]
+    fn delay() {
+        let field = |name: &str| DecodedValue::new(name, 0..0, Value::Int(0));
+        let mut header = DecodedValue::new(
+            "current_block_header",
+            0..0,
+            Value::Node(vec![field("level"), field("predecessor"), field("operations_hash")]),
+        );
+        header.message_range = 4..12;
+        // the variant is named as in the encoding
+        let message = vec![DecodedValue::new("CurrentHead", 0..0, Value::Node(vec![header]))];
+        let origin = |frame, time| Origin {
+            conversation: "a -> b".to_owned(),
+            source: "a".to_owned(),
+            direction: "remote".to_owned(),
+            frame,
+            time,
+            relative_time: time,
+            stream: None,
+            interface: None,
+        };
+        let body = [0; 16];
+        let mut blocks = BlockTimeline::default();
+        blocks.scan(&origin(3, 10.0), &message, &body);
+        blocks.scan(&origin(9, 10.25), &message, &body);
+
+        let mut csv = Vec::new();
+        blocks.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        let last = csv.lines().nth(2).unwrap();
+        assert!(last.ends_with(",CurrentHead,remote,a,a -> b,9,10.250000,250"));
+    }
+}
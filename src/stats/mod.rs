@@ -0,0 +1,130 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+mod points;
+mod blocks;
+mod operations;
+
+use wireshark_epan_adapter::dissector::Tree;
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    path::Path,
+    fs::File,
+    io::{self, Write},
+};
+use self::{points::KnownPoints, blocks::BlockTimeline, operations::OperationTracker};
+use crate::value::DecodedMessage;
+
+/// Where and when the message was seen.
+#[derive(Clone, Debug, Serialize)]
+pub struct Origin {
+    pub conversation: String,
+    /// Address of the peer who sent the message.
+    pub source: String,
+    /// `local` or `remote`, the same as `tezos.source`.
+    pub direction: String,
+    pub frame: u64,
+    /// Seconds since the UNIX epoch.
+    pub time: f64,
+    /// Seconds since the first frame of the capture.
+    pub relative_time: f64,
+    /// The TCP stream index, the same as `tcp.stream`.
+    pub stream: Option<u32>,
+    /// The capture interface, if the capture file records it.
+    pub interface: Option<u32>,
+}
+
+impl Origin {
+    /// Milliseconds passed since the `earlier` origin.
+    pub fn delay_since(&self, earlier: &Origin) -> i64 {
+        ((self.time - earlier.time) * 1000.0).round() as i64
+    }
+}
+
+/// The data aggregated over all conversations of the capture.
+#[derive(Default)]
+pub struct Stats {
+    points: KnownPoints,
+    blocks: BlockTimeline,
+    operations: OperationTracker,
+}
+
+impl Stats {
+    /// Called once for each decoded message, the `body` is the message without chunk headers.
+    pub fn scan(&mut self, origin: &Origin, message: &DecodedMessage, body: &[u8]) {
+        self.points.scan(origin, &message.values);
+        self.blocks.scan(origin, &message.values, body);
+        self.operations.scan(origin, &message.values, body);
+    }
+
+    /// Show what is known about the content of the message.
+    pub fn show(&self, message: &DecodedMessage, node: &mut Tree) {
+        self.points.show(&message.values, node);
+    }
+
+    /// Show what is known about the content of the frame.
+    pub fn show_frame(&self, frame: u64, node: &mut Tree) {
+        self.blocks.show(frame, node);
+        self.operations.show(frame, node);
+    }
+
+    /// Write the known points in the file, csv or json depending on the extension.
+    pub fn export_points<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        export(path, |w| self.points.write_csv(w), |w| self.points.write_json(w))
+    }
+
+    /// Write where and when each block is seen, csv or json depending on the extension.
+    pub fn export_blocks<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        export(path, |w| self.blocks.write_csv(w), |w| self.blocks.write_json(w))
+    }
+
+    /// Write where and when each operation is seen and in which block it is included,
+    /// csv or json depending on the extension.
+    pub fn export_operations<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let operations = &self.operations;
+        export(path, |w| operations.write_csv(w), |w| operations.write_json(w))
+    }
+
+    pub fn clear(&mut self) {
+        *self = Stats::default();
+    }
+}
+
+fn export<P, C, J>(path: P, csv: C, json: J) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    C: FnOnce(&mut dyn Write) -> io::Result<()>,
+    J: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    let is_csv = path
+        .as_ref()
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.eq_ignore_ascii_case("csv"))
+        .unwrap_or(false);
+    let mut file = File::create(path)?;
+    if is_csv {
+        csv(&mut file)
+    } else {
+        json(&mut file)
+    }
+}
+
+/// Quote the CSV field if it contains a separator, a quote or a line break.
+fn csv_field(s: &str) -> Cow<str> {
+    if s.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
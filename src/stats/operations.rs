@@ -0,0 +1,349 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use wireshark_epan_adapter::dissector::{Tree, TreeLeaf};
+use crypto::{hash::HashType, blake2b};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Write},
+};
+use super::{Origin, csv_field};
+use crate::value::{DecodedValue, Value, is_variant};
+
+/// How the operation appears in the message.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    /// The whole operation in `Operation` message.
+    Operation,
+    /// The hash in `GetOperations`, the peer asks for the operation.
+    GetOperations,
+    /// The hash in the mempool of `CurrentHead`.
+    Mempool,
+}
+
+impl OperationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            &OperationKind::Operation => "operation",
+            &OperationKind::GetOperations => "get_operations",
+            &OperationKind::Mempool => "mempool",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Sighting {
+    pub kind: OperationKind,
+    #[serde(flatten)]
+    pub origin: Origin,
+}
+
+/// The operation is in `OperationsForBlocks` of the block.
+#[derive(Clone, Debug, Serialize)]
+pub struct Inclusion {
+    pub block: String,
+    #[serde(flatten)]
+    pub origin: Origin,
+}
+
+#[derive(Default, Serialize)]
+struct OperationRecord {
+    sightings: Vec<Sighting>,
+    included: Option<Inclusion>,
+}
+
+impl OperationRecord {
+    // the peers who sent the operation or announced it in the mempool
+    fn peers(&self) -> BTreeSet<&str> {
+        self.sightings
+            .iter()
+            .filter(|s| s.kind != OperationKind::GetOperations)
+            .map(|s| s.origin.source.as_str())
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+struct Operation<'a> {
+    operation: &'a str,
+    #[serde(flatten)]
+    record: &'a OperationRecord,
+}
+
+/// Where and when each operation is seen, and whether it gets in a block.
+#[derive(Default)]
+pub struct OperationTracker {
+    operations: BTreeMap<String, OperationRecord>,
+    // hashes of the operations seen in the frame
+    frames: BTreeMap<u64, Vec<String>>,
+}
+
+impl OperationTracker {
+    /// The `body` is the whole message, the ranges of the values are relative to it.
+    pub fn scan(&mut self, origin: &Origin, values: &[DecodedValue], body: &[u8]) {
+        let mut found = Found::default();
+        collect(values, body, None, &mut found);
+        for (kind, hash) in found.seen {
+            self.frame(origin.frame, &hash);
+            self.operations
+                .entry(hash)
+                .or_insert_with(OperationRecord::default)
+                .sightings
+                .push(Sighting {
+                    kind,
+                    origin: origin.clone(),
+                });
+        }
+        for (block, hash) in found.included {
+            self.frame(origin.frame, &hash);
+            let record = self
+                .operations
+                .entry(hash)
+                .or_insert_with(OperationRecord::default);
+            if record.included.is_none() {
+                record.included = Some(Inclusion {
+                    block,
+                    origin: origin.clone(),
+                });
+            }
+        }
+    }
+
+    fn frame(&mut self, frame: u64, hash: &str) {
+        let hashes = self.frames.entry(frame).or_insert_with(Vec::new);
+        if !hashes.iter().any(|h| h == hash) {
+            hashes.push(hash.to_owned());
+        }
+    }
+
+    /// Show the operations seen in the frame, when they are seen first,
+    /// who sent them, and where they are included.
+    pub fn show(&self, frame: u64, node: &mut Tree) {
+        for hash in self.frames.get(&frame).into_iter().flatten() {
+            let record = &self.operations[hash];
+            let peers = record.peers().len();
+            let first = record.sightings.first().map(|s| &s.origin);
+
+            let mut text = hash.clone();
+            if let Some(first) = first {
+                let delay = Self::delay(record, frame, first);
+                text += &format!(", first seen in frame {}, +{} ms", first.frame, delay);
+            }
+            text += &format!(", sent by {} peers", peers);
+            match &record.included {
+                &Some(ref i) => {
+                    text += &format!(", in block {} in frame {}", i.block, i.origin.frame)
+                },
+                &None => text += ", not included in a block",
+            }
+
+            let mut operation_node = node.add("operation", 0..0, TreeLeaf::Display(text)).subtree();
+            operation_node.add("hash", 0..0, TreeLeaf::Display(hash));
+            if let Some(first) = first {
+                let delay = Self::delay(record, frame, first);
+                operation_node.add("first_seen", 0..0, TreeLeaf::frame(first.frame));
+                operation_node.add("delay", 0..0, TreeLeaf::dec(delay));
+            }
+            operation_node.add("peers", 0..0, TreeLeaf::dec(peers as _));
+            operation_node.add("included", 0..0, TreeLeaf::bool(record.included.is_some()));
+            if let &Some(ref included) = &record.included {
+                operation_node.add("block", 0..0, TreeLeaf::Display(&included.block));
+                operation_node.add("included_in", 0..0, TreeLeaf::frame(included.origin.frame));
+                if let Some(first) = first {
+                    let delay = included.origin.delay_since(first);
+                    operation_node.add("inclusion_delay", 0..0, TreeLeaf::dec(delay));
+                }
+            }
+        }
+    }
+
+    // milliseconds since the operation is seen first until it is seen in the frame
+    fn delay(record: &OperationRecord, frame: u64, first: &Origin) -> i64 {
+        record
+            .sightings
+            .iter()
+            .map(|s| &s.origin)
+            .find(|o| o.frame == frame)
+            .map(|o| o.delay_since(first))
+            .unwrap_or(0)
+    }
+
+    pub fn write_csv<W>(&self, w: &mut W) -> io::Result<()>
+    where
+        W: Write + ?Sized,
+    {
+        writeln!(w, "operation,kind,block,direction,source,conversation,frame,time,delay_ms")?;
+        for (operation, record) in &self.operations {
+            let first = record.sightings.first().map(|s| &s.origin);
+            let line = |w: &mut W, kind: &str, block: &str, o: &Origin| {
+                let delay = first.map(|first| o.delay_since(first)).unwrap_or(0);
+                writeln!(
+                    w,
+                    "{},{},{},{},{},{},{},{:.6},{}",
+                    operation,
+                    csv_field(kind),
+                    block,
+                    o.direction,
+                    csv_field(&o.source),
+                    csv_field(&o.conversation),
+                    o.frame,
+                    o.time,
+                    delay,
+                )
+            };
+            for s in &record.sightings {
+                line(w, s.kind.as_str(), "", &s.origin)?;
+            }
+            if let &Some(ref included) = &record.included {
+                line(w, "included", &included.block, &included.origin)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_json<W>(&self, w: &mut W) -> io::Result<()>
+    where
+        W: Write + ?Sized,
+    {
+        let operations = self
+            .operations
+            .iter()
+            .map(|(operation, record)| Operation { operation, record })
+            .collect::<Vec<_>>();
+        serde_json::to_writer_pretty(w, &operations).map_err(io::Error::from)
+    }
+}
+
+#[derive(Default)]
+struct Found {
+    seen: Vec<(OperationKind, String)>,
+    // the block and the operation included in it
+    included: Vec<(String, String)>,
+}
+
+// the whole operation has these fields
+fn is_operation(children: &[DecodedValue]) -> bool {
+    ["branch", "data"]
+        .iter()
+        .all(|name| children.iter().any(|c| c.name == *name))
+}
+
+fn operation_hash(value: &DecodedValue, body: &[u8]) -> Option<String> {
+    let bytes = body.get(value.message_range.clone())?;
+    Some(HashType::OperationHash.bytes_to_string(&blake2b::digest_256(bytes)))
+}
+
+// the block of `OperationsForBlocks`
+fn block_hash(children: &[DecodedValue]) -> Option<String> {
+    let operations_for_block = children.iter().find(|c| c.name == "operations_for_block")?;
+    match &operations_for_block.value {
+        &Value::Node(ref children) => children.iter().find_map(|c| match &c.value {
+            &Value::Hash(ref hash) if c.name == "hash" => Some(hash.clone()),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+// the `block` is known inside `OperationsForBlocks`
+fn collect(values: &[DecodedValue], body: &[u8], block: Option<&str>, found: &mut Found) {
+    for value in values {
+        match &value.value {
+            &Value::Node(ref children) if is_operation(children) => {
+                if let Some(hash) = operation_hash(value, body) {
+                    match block {
+                        Some(block) => found.included.push((block.to_owned(), hash)),
+                        None => found.seen.push((OperationKind::Operation, hash)),
+                    }
+                }
+            },
+            &Value::Node(ref children) if is_variant(&value.name, "operations_for_blocks") => {
+                let block = block_hash(children);
+                collect(children, body, block.as_ref().map(String::as_str), found);
+            },
+            &Value::List(ref items) => {
+                let kind = match value.name.as_str() {
+                    "get_operations" => OperationKind::GetOperations,
+                    "known_valid" | "pending" => OperationKind::Mempool,
+                    _ => {
+                        collect(items, body, block, found);
+                        continue;
+                    },
+                };
+                for item in items {
+                    if let &Value::Hash(ref hash) = &item.value {
+                        found.seen.push((kind, hash.clone()));
+                    }
+                }
+            },
+            _ => collect(value.children(), body, block, found),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OperationTracker, Origin, operation_hash};
+    use crate::value::{DecodedValue, Value};
+
+    #[test]
+    fn mempool_then_block() {
+        let origin = |source: &str, frame, time| Origin {
+            conversation: format!("{} -> b", source),
+            source: source.to_owned(),
+            direction: "remote".to_owned(),
+            frame,
+            time,
+            relative_time: time,
+            stream: None,
+            interface: None,
+        };
+        let body = [1; 64];
+        let mut operation = DecodedValue::new(
+            "operations",
+            0..0,
+            Value::Node(vec![
+                DecodedValue::new("branch", 0..0, Value::Hash("BLock".to_owned())),
+                DecodedValue::new("data", 0..0, Value::Bytes(vec![1; 8])),
+            ]),
+        );
+        operation.message_range = 8..48;
+        let hash = operation_hash(&operation, &body).unwrap();
+        let item = DecodedValue::new("item", 0..0, Value::Hash(hash.clone()));
+        let mempool = vec![DecodedValue::new("known_valid", 0..0, Value::List(vec![item]))];
+        let mut tracker = OperationTracker::default();
+        tracker.scan(&origin("a", 3, 1.0), &mempool, &body);
+        tracker.scan(&origin("b", 5, 1.5), &mempool, &body);
+        let block = vec![DecodedValue::new(
+            "OperationsForBlocks",
+            0..0,
+            Value::Node(vec![
+                DecodedValue::new(
+                    "operations_for_block",
+                    0..0,
+                    Value::Node(vec![DecodedValue::new(
+                        "hash",
+                        0..0,
+                        Value::Hash("BLockHash".to_owned()),
+                    )]),
+                ),
+                operation,
+            ]),
+        )];
+        tracker.scan(&origin("c", 9, 2.0), &block, &body);
+
+        let record = &tracker.operations[&hash];
+        assert_eq!(record.peers().len(), 2);
+        let included = record.included.as_ref().unwrap();
+        assert_eq!(included.block, "BLockHash");
+        assert_eq!(included.origin.delay_since(&record.sightings[0].origin), 1000);
+
+        let mut csv = Vec::new();
+        tracker.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 4);
+        assert!(csv.lines().nth(2).unwrap().contains(",mempool,,remote,b,b -> b,5,"));
+    }
+}
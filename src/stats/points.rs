@@ -0,0 +1,224 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use wireshark_epan_adapter::dissector::{Tree, TreeLeaf};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Write},
+};
+use super::{Origin, csv_field};
+use crate::value::{DecodedValue, Value, is_variant};
+
+/// The peer advertised the point in the message of some kind.
+#[derive(Clone, Debug, Serialize)]
+pub struct Advertisement {
+    /// `advertise`, `swap_request`, `swap_ack` or the name of the nack variant.
+    pub kind: String,
+    #[serde(flatten)]
+    pub origin: Origin,
+}
+
+#[derive(Serialize)]
+struct KnownPoint<'a> {
+    point: &'a str,
+    advertisements: &'a [Advertisement],
+}
+
+/// Points collected from `Advertise`, `SwapRequest`, `SwapAck` and `Nack` messages.
+#[derive(Default)]
+pub struct KnownPoints {
+    points: BTreeMap<String, Vec<Advertisement>>,
+}
+
+impl KnownPoints {
+    pub fn scan(&mut self, origin: &Origin, values: &[DecodedValue]) {
+        let mut found = Vec::new();
+        collect(values, None, &mut found);
+        for (kind, point) in found {
+            self.points
+                .entry(point.to_owned())
+                .or_insert_with(Vec::new)
+                .push(Advertisement {
+                    kind: snake_case(kind),
+                    origin: origin.clone(),
+                });
+        }
+    }
+
+    pub fn show(&self, values: &[DecodedValue], node: &mut Tree) {
+        let mut found = Vec::new();
+        collect(values, None, &mut found);
+        for (_, point) in found {
+            if let Some(advertisements) = self.points.get(point) {
+                let peers = advertisements
+                    .iter()
+                    .map(|a| a.origin.source.as_str())
+                    .collect::<BTreeSet<_>>();
+                let first = &advertisements[0].origin;
+                let text = format!(
+                    "{}, advertised {} times by {} peers, first by {} in frame {}",
+                    point,
+                    advertisements.len(),
+                    peers.len(),
+                    first.source,
+                    first.frame,
+                );
+                node.add("known_point", 0..0, TreeLeaf::Display(text));
+            }
+        }
+    }
+
+    pub fn write_csv<W>(&self, w: &mut W) -> io::Result<()>
+    where
+        W: Write + ?Sized,
+    {
+        writeln!(w, "point,kind,source,conversation,frame,time")?;
+        for (point, advertisements) in &self.points {
+            for a in advertisements {
+                let o = &a.origin;
+                writeln!(
+                    w,
+                    "{},{},{},{},{},{:.6}",
+                    csv_field(point),
+                    csv_field(&a.kind),
+                    csv_field(&o.source),
+                    csv_field(&o.conversation),
+                    o.frame,
+                    o.time,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_json<W>(&self, w: &mut W) -> io::Result<()>
+    where
+        W: Write + ?Sized,
+    {
+        let points = self
+            .points
+            .iter()
+            .map(|(point, advertisements)| KnownPoint {
+                point,
+                advertisements,
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_writer_pretty(w, &points).map_err(io::Error::from)
+    }
+}
+
+// the point lists are in these messages
+fn carries_points(name: &str) -> bool {
+    ["advertise", "swap_request", "swap_ack"]
+        .iter()
+        .any(|variant| is_variant(name, variant))
+        || name.to_lowercase().starts_with("nack")
+}
+
+// the variant names are in CamelCase, `SwapRequest` becomes `swap_request`
+fn snake_case(name: &str) -> String {
+    let mut s = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i != 0 {
+            s.push('_');
+        }
+        s.push(c.to_ascii_lowercase());
+    }
+    s
+}
+
+// find all strings inside the messages carrying points, the strings are points
+fn collect<'a>(
+    values: &'a [DecodedValue],
+    kind: Option<&'a str>,
+    out: &mut Vec<(&'a str, &'a str)>,
+) {
+    for value in values {
+        let kind = match kind {
+            Some(kind) => Some(kind),
+            None if carries_points(&value.name) => Some(value.name.as_str()),
+            None => None,
+        };
+        match (&value.value, kind) {
+            (&Value::Node(ref children), _)
+            | (&Value::List(ref children), _)
+            | (&Value::Recursive(ref children), _) => collect(children, kind, out),
+            (&Value::String(ref point), Some(kind)) => out.push((kind, point)),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KnownPoints, Origin};
+    use crate::value::DecodedMessage;
+    use tezos_messages::p2p::{
+        binary_message::BinaryMessage,
+        encoding::{
+            advertise::AdvertiseMessage,
+            peer::{PeerMessage, PeerMessageResponse},
+        },
+    };
+    use std::ops::Range;
+
+    fn origin(source: &str) -> Origin {
+        Origin {
+            conversation: "a -> b".to_owned(),
+            source: source.to_owned(),
+            direction: "local".to_owned(),
+            frame: 7,
+            time: 1.5,
+            relative_time: 0.5,
+            stream: Some(3),
+            interface: None,
+        }
+    }
+
+    #[test]
+    fn advertise() {
+        let addresses = ["1.2.3.4:9732".parse().unwrap(), "5.6.7.8:9732".parse().unwrap()];
+        let message = PeerMessage::Advertise(AdvertiseMessage::new(&addresses));
+        let data = PeerMessageResponse::from(message).as_bytes().unwrap();
+        let chunks: Vec<Range<usize>> = vec![0..0, 0..0, 0..0, 0..data.len()];
+        let message = DecodedMessage::decode(data.as_ref(), chunks.as_ref(), 3).unwrap();
+        assert!(message.error.is_none());
+
+        let mut points = KnownPoints::default();
+        points.scan(&origin("a"), &message.values);
+        points.scan(&origin("a"), &message.values);
+
+        let mut csv = Vec::new();
+        points.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 5);
+        assert_eq!(csv.lines().nth(1), Some("1.2.3.4:9732,advertise,a,a -> b,7,1.500000"));
+
+        let mut json = Vec::new();
+        points.write_json(&mut json).unwrap();
+        let json = serde_json::from_slice::<serde_json::Value>(&json).unwrap();
+        assert_eq!(json[0]["advertisements"][0]["stream"], 3);
+    }
+
+    #[test]
+    fn quoted_csv() {
+        // the advertise message, tag 0x0003, carrying the point `x,"y"` and a line break
+        let point = b"x,\"y\"\nz";
+        let mut data = vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00];
+        data.push(point.len() as u8);
+        data.extend_from_slice(point);
+        data[3] = (data.len() - 4) as u8;
+        let chunks: Vec<Range<usize>> = vec![0..0, 0..0, 0..0, 0..data.len()];
+        let message = DecodedMessage::decode(data.as_ref(), chunks.as_ref(), 3).unwrap();
+
+        let mut points = KnownPoints::default();
+        points.scan(&origin("a,b"), &message.values);
+
+        let mut csv = Vec::new();
+        points.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let line = "\"x,\"\"y\"\"\nz\",advertise,\"a,b\",a -> b,7,1.500000\n";
+        assert!(csv.ends_with(line));
+    }
+}
@@ -0,0 +1,244 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use serde::{Serialize, Deserialize};
+use std::{ops::Range, fmt};
+use super::{decoded::{DecodedValue, Value}, named::is_variant};
+
+/// The content of the chunk, the encrypted content and its MAC fit in 16 bits,
+/// the Tezos node applies the same limit to the plain chunk.
+pub const MAX_CHUNK_CONTENT: usize = 0xffff - 16;
+
+/// The validation pass is one byte, so the tree of operation lists has at most 256 leaves,
+/// the operations path is the only recursive value of the protocol.
+pub const MAX_PATH_DEPTH: usize = 8;
+
+// the limits of the Tezos node, the size of the variant in bytes
+const MESSAGE_SIZE_LIMITS: &[(&str, usize)] = &[
+    ("block_header", 8 * 1024),
+    // the branch and at most 32 KiB of data
+    ("operation", 32 + 32 * 1024),
+];
+
+// the limits of the Tezos node, how many items the list might contain
+const LIST_LENGTH_LIMITS: &[(&str, usize)] = &[
+    ("get_block_headers", 10),
+    ("get_operations", 10),
+    ("get_protocols", 10),
+    ("get_operation_hashes_for_blocks", 10),
+    ("get_operations_for_blocks", 10),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    ChunkSize,
+    MessageSize,
+    ListLength,
+    PathDepth,
+    TrailingBytes,
+    NonCanonical,
+}
+
+impl ViolationKind {
+    /// The name of the expert info, registered in the plugin.
+    pub fn expert(&self) -> &'static str {
+        match self {
+            &ViolationKind::ChunkSize => "tezos.conformance.chunk_size",
+            &ViolationKind::MessageSize => "tezos.conformance.message_size",
+            &ViolationKind::ListLength => "tezos.conformance.list_length",
+            &ViolationKind::PathDepth => "tezos.conformance.path_depth",
+            &ViolationKind::TrailingBytes => "tezos.conformance.trailing_bytes",
+            &ViolationKind::NonCanonical => "tezos.conformance.non_canonical",
+        }
+    }
+}
+
+/// The message is decoded, but the peer should not send it this way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    /// Where the offending bytes are in the buffer.
+    pub range: Range<usize>,
+    pub description: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description)
+    }
+}
+
+impl Violation {
+    /// The `range` is the whole chunk, the `content` is its length without the MAC.
+    pub fn chunk_size(range: Range<usize>, content: usize) -> Self {
+        let description = format!(
+            "the chunk content is {} bytes, at most {} allowed",
+            content, MAX_CHUNK_CONTENT,
+        );
+        Violation {
+            kind: ViolationKind::ChunkSize,
+            range,
+            description,
+        }
+    }
+
+    pub fn trailing_bytes(range: Range<usize>) -> Self {
+        let description = format!("{} bytes after the end of the message", range.len());
+        Violation {
+            kind: ViolationKind::TrailingBytes,
+            range,
+            description,
+        }
+    }
+}
+
+/// Check the decoded values, the `body` is the whole message,
+/// the message ranges of the values are relative to it.
+pub fn check(values: &[DecodedValue], body: &[u8], out: &mut Vec<Violation>) {
+    check_nested(values, body, false, out)
+}
+
+// `nested` if the values are inside the recursive value, its depth is already checked
+fn check_nested(values: &[DecodedValue], body: &[u8], nested: bool, out: &mut Vec<Violation>) {
+    for value in values {
+        match &value.value {
+            &Value::List(ref items) => {
+                check_list(value, items, out);
+                check_nested(items, body, nested, out);
+            },
+            &Value::Recursive(ref levels) => {
+                // each level is followed by the deeper one, the recursive value
+                let depth = recursion_depth(levels) + 1;
+                if !nested && depth > MAX_PATH_DEPTH {
+                    out.push(Violation {
+                        kind: ViolationKind::PathDepth,
+                        range: value.range.clone(),
+                        description: format!(
+                            "{} is {} levels deep, at most {} possible",
+                            value.name, depth, MAX_PATH_DEPTH,
+                        ),
+                    });
+                }
+                check_nested(levels, body, true, out);
+            },
+            &Value::Node(ref children) => {
+                check_size(value, out);
+                check_nested(children, body, nested, out);
+            },
+            &Value::BigInt(ref number) => {
+                let bytes = body.get(value.message_range.clone()).unwrap_or(&[]);
+                // the most significant group is the last, it should not be zero
+                if bytes.len() > 1 && bytes[bytes.len() - 1] == 0 {
+                    out.push(Violation {
+                        kind: ViolationKind::NonCanonical,
+                        range: value.range.clone(),
+                        description: format!(
+                            "{} is encoded in {} bytes with trailing zero",
+                            number,
+                            bytes.len(),
+                        ),
+                    });
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+// the variants are named like `BlockHeader`, but the limits like `block_header`
+fn check_size(value: &DecodedValue, out: &mut Vec<Violation>) {
+    let limit = MESSAGE_SIZE_LIMITS
+        .iter()
+        .find(|&&(variant, _)| is_variant(&value.name, variant))
+        .map(|&(_, limit)| limit);
+    let size = value.message_range.len();
+    // the variant contains the field of the same name, report once
+    let reported = out
+        .last()
+        .map(|v| {
+            v.kind == ViolationKind::MessageSize
+                && v.range.start <= value.range.start
+                && value.range.end <= v.range.end
+        })
+        .unwrap_or(false);
+    match limit {
+        Some(limit) if size > limit && !reported => out.push(Violation {
+            kind: ViolationKind::MessageSize,
+            range: value.range.clone(),
+            description: format!("{} is {} bytes, at most {} allowed", value.name, size, limit),
+        }),
+        _ => (),
+    }
+}
+
+fn recursion_depth(values: &[DecodedValue]) -> usize {
+    values
+        .iter()
+        .map(|value| match &value.value {
+            &Value::Recursive(ref values) => recursion_depth(values) + 1,
+            _ => recursion_depth(value.children()),
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn check_list(value: &DecodedValue, items: &[DecodedValue], out: &mut Vec<Violation>) {
+    let limit = LIST_LENGTH_LIMITS
+        .iter()
+        .find(|&&(name, _)| name == value.name)
+        .map(|&(_, limit)| limit);
+    match limit {
+        Some(limit) if items.len() > limit => out.push(Violation {
+            kind: ViolationKind::ListLength,
+            range: value.range.clone(),
+            description: format!(
+                "{} has {} items, at most {} allowed",
+                value.name,
+                items.len(),
+                limit,
+            ),
+        }),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, ViolationKind};
+    use crate::value::{DecodedValue, Value};
+
+    #[test]
+    fn violations() {
+        let hash = || DecodedValue::new("item", 0..0, Value::Hash("B".to_owned()));
+        let hashes = (0..11).map(|_| hash()).collect();
+        let mut z = DecodedValue::new("fee", 0..0, Value::BigInt("1".to_owned()));
+        z.message_range = 0..2;
+        let children = vec![
+            DecodedValue::new("get_block_headers", 0..0, Value::List(hashes)),
+            z,
+        ];
+        let values = vec![DecodedValue::new("GetBlockHeaders", 0..0, Value::Node(children))];
+
+        let mut out = Vec::new();
+        check(&values, &[0x81, 0x00], &mut out);
+        let kinds = out.iter().map(|v| v.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![ViolationKind::ListLength, ViolationKind::NonCanonical]);
+    }
+
+    #[test]
+    fn path_depth() {
+        let path = |depth: usize| {
+            (0..depth).fold(Vec::new(), |deeper, _| {
+                vec![DecodedValue::new("path", 0..0, Value::Recursive(deeper))]
+            })
+        };
+        let mut out = Vec::new();
+        check(&path(8), &[], &mut out);
+        assert!(out.is_empty());
+        // reported once, at the outermost level
+        check(&path(10), &[], &mut out);
+        let kinds = out.iter().map(|v| v.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![ViolationKind::PathDepth]);
+    }
+}
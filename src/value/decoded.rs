@@ -0,0 +1,488 @@
+// Copyright (c) SimpleStaking and Tezedge Contributors
+// SPDX-License-Identifier: MIT
+
+use wireshark_epan_adapter::dissector::{Tree, TreeLeaf};
+use tezos_encoding::encoding::HasEncoding;
+use tezos_messages::p2p::encoding::{
+    ack::AckMessage, metadata::MetadataMessage, peer::PeerMessageResponse,
+    connection::ConnectionMessage,
+};
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use chrono::NaiveDateTime;
+use std::{fmt, mem, ops::Range};
+use super::{
+    message::{ChunkedData, ChunkedDataOffset, DecodingError, HasBodyRange},
+    fields::Named,
+    conformance::{self, Violation},
+};
+use crate::{range_tool::intersect, names::Names};
+
+/// The decoded field, owned and independent of the Wireshark tree.
+/// The `range` is in the coordinates of the buffer of the conversation,
+/// the `message_range` is in the coordinates of the message,
+/// that is the concatenation of bodies of its chunks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DecodedValue {
+    pub name: String,
+    pub range: Range<usize>,
+    pub message_range: Range<usize>,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Value {
+    Node(Vec<DecodedValue>),
+    /// The items are named `item`, they are shown with their index.
+    List(Vec<DecodedValue>),
+    /// The values of the lazy encoding, shown using the fields of the outermost such value.
+    Recursive(Vec<DecodedValue>),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// Arbitrary precision number, `Z` or `Mutez`, in hex.
+    BigInt(String),
+    String(String),
+    #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+    Bytes(Vec<u8>),
+    /// Base58check encoded with the prefix of its type, like `NetXdQprcVkpaWU`.
+    Hash(String),
+    /// Seconds since the epoch.
+    Timestamp(i64),
+    /// Whether the optional value is present, the name ends with `.present`.
+    Presence(bool),
+    /// The id of the variant of tagged union, and its name if the id is known.
+    Tag {
+        id: u16,
+        variant: Option<String>,
+    },
+}
+
+fn to_hex<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&hex::encode(bytes))
+}
+
+fn from_hex<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let s = String::deserialize(deserializer)?;
+    hex::decode(s).map_err(D::Error::custom)
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            &Value::Node(ref children)
+            | &Value::List(ref children)
+            | &Value::Recursive(ref children) => write!(f, "{} items", children.len()),
+            &Value::Int(value) => write!(f, "{}", value),
+            &Value::Float(value) => write!(f, "{}", value),
+            &Value::Bool(value) => write!(f, "{}", value),
+            &Value::BigInt(ref value) => write!(f, "{}", value),
+            &Value::String(ref value) => write!(f, "{}", value),
+            &Value::Bytes(ref value) => write!(f, "{}", hex::encode(value)),
+            &Value::Hash(ref value) => write!(f, "{}", value),
+            &Value::Timestamp(value) => write!(f, "{}", NaiveDateTime::from_timestamp(value, 0)),
+            &Value::Presence(true) => write!(f, "present"),
+            &Value::Presence(false) => write!(f, "absent"),
+            &Value::Tag {
+                id,
+                variant: Some(ref variant),
+            } => write!(f, "{} (0x{:04x})", variant, id),
+            &Value::Tag { id, variant: None } => write!(f, "unknown tag 0x{:04x}", id),
+        }
+    }
+}
+
+impl DecodedValue {
+    /// The message range is unknown at this point, see `DecodedMessage::decode`.
+    pub fn new(name: &str, range: Range<usize>, value: Value) -> Self {
+        DecodedValue {
+            name: name.to_owned(),
+            range,
+            message_range: 0..0,
+            value,
+        }
+    }
+
+    /// The children of the node, the items of the list or the values of the recursion.
+    pub fn children(&self) -> &[DecodedValue] {
+        match &self.value {
+            &Value::Node(ref children)
+            | &Value::List(ref children)
+            | &Value::Recursive(ref children) => children,
+            _ => &[],
+        }
+    }
+
+    /// Approximate bytes the value and its children occupy in the memory.
+    pub fn in_memory(&self) -> usize {
+        let content = match &self.value {
+            &Value::BigInt(ref s) | &Value::String(ref s) | &Value::Hash(ref s) => s.capacity(),
+            &Value::Bytes(ref bytes) => bytes.capacity(),
+            &Value::Tag {
+                variant: Some(ref variant),
+                ..
+            } => variant.capacity(),
+            _ => 0,
+        };
+        let children = self
+            .children()
+            .iter()
+            .map(DecodedValue::in_memory)
+            .sum::<usize>();
+        mem::size_of::<Self>() + self.name.capacity() + content + children
+    }
+
+    /// Put the value on the tree, the space is the range of the frame in the buffer.
+    /// If `full` is false, only values intersecting the space are shown.
+    /// Hashes are shown along with their names if known.
+    pub fn show(&self, space: &Range<usize>, node: &mut Tree, full: bool, names: &Names) {
+        self.show_item(space, node, full, names, None, None)
+    }
+
+    // the `index` is known if the value is an item of the list,
+    // the `recursion` is the path of the outermost recursive value if inside of it
+    fn show_item(
+        &self,
+        space: &Range<usize>,
+        node: &mut Tree,
+        full: bool,
+        names: &Names,
+        index: Option<usize>,
+        recursion: Option<&str>,
+    ) {
+        let outside = self.range.start >= space.end
+            || self.range.end < space.start
+            || (self.range.end == space.start && !self.range.is_empty());
+        if !full && outside {
+            return;
+        }
+        if let &Value::Recursive(ref values) = &self.value {
+            // the fields are registered only for the outermost value, the same at any depth
+            let base = recursion.unwrap_or_else(|| node.path()).to_owned();
+            let mut node = node.rebased(&base);
+            for value in values {
+                value.show_item(space, &mut node, full, names, index, Some(&base));
+            }
+            return;
+        }
+        let range = intersect(space, self.range.clone());
+        let mut item = match &self.value {
+            &Value::Node(_) | &Value::List(_) | &Value::Recursive(_) => {
+                node.add(&self.name, range, TreeLeaf::nothing())
+            },
+            &Value::Int(value) => node.add(&self.name, range, TreeLeaf::dec(value)),
+            &Value::Hash(ref hash) => {
+                node.add(&self.name, range, TreeLeaf::Display(names.display(hash)))
+            },
+            &Value::Presence(present) => {
+                let mut item = node.add(&self.name, range, TreeLeaf::bool(present));
+                // shown like `field: absent`, but filtered by `*.present`
+                let field = self.name.trim_end_matches(".present");
+                item.set_text(format!("{}: {}", field, self.value));
+                item
+            },
+            &Value::Tag { id, .. } => {
+                // filtered by the number, but shown with the name of the variant
+                let mut item = node.add(&self.name, range, TreeLeaf::dec(id as _));
+                item.set_text(format!("{}: {}", self.name, self.value));
+                item
+            },
+            value => node.add(&self.name, range, TreeLeaf::Display(value)),
+        };
+        if let Some(index) = index {
+            item.prepend_text(format!("[{}] ", index));
+        }
+        match &self.value {
+            &Value::Node(ref children) => {
+                let mut sub_node = item.subtree();
+                for child in children {
+                    child.show_item(space, &mut sub_node, full, names, None, recursion);
+                }
+            },
+            &Value::List(ref items) => {
+                // both describe the whole list
+                let range = intersect(space, self.range.clone());
+                let mut sub_node = item.subtree();
+                sub_node.add("count", range.clone(), TreeLeaf::dec(items.len() as _));
+                sub_node.add("size", range, TreeLeaf::dec(self.message_range.len() as _));
+                for (index, list_item) in items.iter().enumerate() {
+                    list_item.show_item(space, &mut sub_node, full, names, Some(index), recursion);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn set_message_range<C>(&mut self, chunks: &[C])
+    where
+        C: HasBodyRange,
+    {
+        self.message_range =
+            to_message(chunks, self.range.start)..to_message(chunks, self.range.end);
+        if let &mut Value::Node(ref mut children)
+        | &mut Value::List(ref mut children)
+        | &mut Value::Recursive(ref mut children) = &mut self.value
+        {
+            children
+                .iter_mut()
+                .for_each(|child| child.set_message_range(chunks));
+        }
+    }
+}
+
+// convert the position in the buffer into the position in the message,
+// the `chunks` are only those belonging to the message
+fn to_message<C>(chunks: &[C], position: usize) -> usize
+where
+    C: HasBodyRange,
+{
+    let mut skipped = 0;
+    for chunk in chunks {
+        let body = chunk.body();
+        if position <= body.end {
+            return skipped + position.saturating_sub(body.start);
+        }
+        skipped += body.len();
+    }
+    skipped
+}
+
+/// Find all values with the name, at any depth.
+pub fn find<'a>(values: &'a [DecodedValue], name: &str, out: &mut Vec<&'a DecodedValue>) {
+    for value in values {
+        if value.name == name {
+            out.push(value);
+        }
+        find(value.children(), name, out);
+    }
+}
+
+/// The message decoded once and cached, it occupies one or more chunks.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DecodedMessage {
+    pub chunks: Range<usize>,
+    pub range: Range<usize>,
+    pub values: Vec<DecodedValue>,
+    pub error: Option<DecodingError>,
+    /// The message is decoded, but does not conform to the protocol.
+    pub violations: Vec<Violation>,
+}
+
+impl DecodedMessage {
+    /// Decode the message starting at `first_chunk`,
+    /// the chunks should be decrypted, the type of the message depends on its position.
+    /// Returns `None` if the message needs more chunks.
+    pub fn decode<C>(data: &[u8], chunks: &[C], first_chunk: usize) -> Option<Self>
+    where
+        C: HasBodyRange,
+    {
+        Self::decode_inner(ChunkedData::new(data, chunks), data, chunks, first_chunk)
+    }
+
+    /// Decode the message which will not get more chunks, because the capture is over,
+    /// the error tells why it is not complete.
+    pub fn decode_final<C>(data: &[u8], chunks: &[C], first_chunk: usize) -> Self
+    where
+        C: HasBodyRange,
+    {
+        let chunked = ChunkedData::complete(data, chunks);
+        Self::decode_inner(chunked, data, chunks, first_chunk)
+            .expect("the complete data is never waiting for more")
+    }
+
+    fn decode_inner<C>(
+        chunked: ChunkedData<'_, C>,
+        data: &[u8],
+        chunks: &[C],
+        first_chunk: usize,
+    ) -> Option<Self>
+    where
+        C: HasBodyRange,
+    {
+        let (encoding, base) = match first_chunk {
+            0 => (ConnectionMessage::encoding(), ConnectionMessage::NAME),
+            1 => (MetadataMessage::encoding(), MetadataMessage::NAME),
+            2 => (AckMessage::encoding(), AckMessage::NAME),
+            _ => (PeerMessageResponse::encoding(), PeerMessageResponse::NAME),
+        };
+        let mut offset = ChunkedDataOffset {
+            chunks_offset: first_chunk,
+            data_offset: chunks[first_chunk].body().start,
+        };
+        let mut values = Vec::new();
+        let error = match chunked.decode(&mut offset, &encoding, base, &mut values) {
+            Ok(()) => None,
+            Err(DecodingError::NotEnoughData) if !chunked.is_bounded() => return None,
+            Err(e) => Some(e),
+        };
+
+        // the next message starts at the next chunk
+        let at_end = chunks
+            .get(offset.chunks_offset)
+            .map(|c| offset.data_offset == c.body().end)
+            .unwrap_or(true);
+        let mut violations = Vec::new();
+        if error.is_none() && !at_end {
+            let trailing = offset.data_offset..chunks[offset.chunks_offset].body().end;
+            violations.push(Violation::trailing_bytes(trailing));
+        }
+        let end = usize::min(offset.chunks_offset + 1, chunks.len());
+        let end = usize::max(end, first_chunk + 1);
+
+        let chunks = &chunks[first_chunk..end];
+        values
+            .iter_mut()
+            .for_each(|value| value.set_message_range(chunks));
+        let body = chunks
+            .iter()
+            .flat_map(|c| data[c.body()].iter().cloned())
+            .collect::<Vec<_>>();
+        conformance::check(&values, &body, &mut violations);
+        Some(DecodedMessage {
+            chunks: first_chunk..end,
+            range: chunks[0].body().start..chunks[chunks.len() - 1].body().end,
+            values,
+            error,
+            violations,
+        })
+    }
+
+    /// Approximate bytes the decoded values occupy in the memory.
+    pub fn in_memory(&self) -> usize {
+        self.values.iter().map(DecodedValue::in_memory).sum()
+    }
+
+    /// Show the whole message if `full`, otherwise only the part inside the space.
+    pub fn show(&self, space: &Range<usize>, node: &mut Tree, full: bool, names: &Names) {
+        for value in &self.values {
+            value.show(space, node, full, names);
+        }
+        // the name of the chain is a separated field, so it is easy to filter by it
+        let mut chain_ids = Vec::new();
+        find(&self.values, "chain_id", &mut chain_ids);
+        for chain_id in chain_ids {
+            if let &Value::Hash(ref hash) = &chain_id.value {
+                let name = names.get(hash).unwrap_or("unknown");
+                node.add("chain_name", 0..0, TreeLeaf::Display(name));
+            }
+        }
+        if let &Some(ref e) = &self.error {
+            let range = e.range().map(|r| intersect(space, r)).unwrap_or(0..0);
+            node.add("decoding_error", range, TreeLeaf::Display(e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+    use super::{DecodedMessage, DecodedValue, DecodingError, Value};
+    use crate::{names::Names, value::TezosEncoded};
+    use wireshark_epan_adapter::dissector::{HasFields, Tree};
+    use tezos_messages::p2p::encoding::peer::PeerMessageResponse;
+
+    #[test]
+    fn metadata_message() {
+        // two plain chunks, the length prefix is not a part of the body
+        let data = [0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0xff, 0x00];
+        let chunks: Vec<Range<usize>> = vec![2..4, 6..8];
+        let message = DecodedMessage::decode(data.as_ref(), chunks.as_ref(), 1).unwrap();
+        assert_eq!(message.chunks, 1..2);
+        assert!(message.error.is_none());
+
+        let expected = DecodedValue {
+            name: "metadata_message".to_owned(),
+            range: 6..8,
+            message_range: 0..2,
+            value: Value::Node(vec![
+                DecodedValue {
+                    name: "disable_mempool".to_owned(),
+                    range: 6..7,
+                    message_range: 0..1,
+                    value: Value::Bool(true),
+                },
+                DecodedValue {
+                    name: "private_node".to_owned(),
+                    range: 7..8,
+                    message_range: 1..2,
+                    value: Value::Bool(false),
+                },
+            ]),
+        };
+        assert_eq!(message.values, vec![expected]);
+
+        let json = serde_json::to_string(&message.values).unwrap();
+        let values: Vec<DecodedValue> = serde_json::from_str(&json).unwrap();
+        assert_eq!(values, message.values);
+    }
+
+    #[test]
+    fn bytes_as_hex() {
+        let value = DecodedValue::new("data", 0..2, Value::Bytes(vec![0xab, 0xcd]));
+        let json = serde_json::to_value(&value).unwrap();
+        assert_eq!(json["value"]["bytes"], "abcd");
+    }
+
+    #[test]
+    fn truncated_message_waits() {
+        // the peer message declares 100 bytes, only 2 are buffered so far
+        let data = [0x00, 0x00, 0x00, 0x64, 0x00, 0x01];
+        let chunks: Vec<Range<usize>> = vec![0..0, 0..0, 0..0, 0..6];
+        assert!(DecodedMessage::decode(data.as_ref(), chunks.as_ref(), 3).is_none());
+        // unless the capture is over
+        let message = DecodedMessage::decode_final(data.as_ref(), chunks.as_ref(), 3);
+        match message.error {
+            Some(DecodingError::DynamicLengthOverflow {
+                declared: 100,
+                available: 2,
+                ..
+            }) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn list_items() {
+        // the advertise message, tag 0x0003, with two points
+        let data = [
+            0x00, 0x00, 0x00, 0x0e, 0x00, 0x03, 0x00, 0x00, 0x00, 0x03, b'a', b'b', b'c', 0x00,
+            0x00, 0x00, 0x01, b'd',
+        ];
+        let chunks: Vec<Range<usize>> = vec![0..0, 0..0, 0..0, 0..data.len()];
+        let message = DecodedMessage::decode(data.as_ref(), chunks.as_ref(), 3).unwrap();
+        assert!(message.error.is_none());
+
+        let fields = TezosEncoded::<PeerMessageResponse>::fields();
+        let mut node = Tree::recorder("tezos", &fields);
+        message.show(&(0..data.len()), &mut node, true, &Names::default());
+        let records = node.records();
+        let field = |suffix: &str| {
+            records
+                .iter()
+                .filter(|r| r.path.ends_with(suffix))
+                .collect::<Vec<_>>()
+        };
+
+        // the list of messages, and the list of points
+        let count = field(".count");
+        assert_eq!(count.len(), 2);
+        assert_eq!((count[0].value.as_str(), count[0].range.clone()), ("1", 4..18));
+        assert_eq!((count[1].value.as_str(), count[1].range.clone()), ("2", 6..18));
+        let size = field(".size");
+        assert_eq!((size[1].value.as_str(), size[1].range.clone()), ("12", 6..18));
+
+        let points = field(".id.item");
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].prefix, "[0] ");
+        assert_eq!((points[1].value.as_str(), points[1].range.clone()), ("d", 17..18));
+        assert_eq!(points[1].prefix, "[1] ");
+    }
+}
@@ -1,8 +1,14 @@
 // Copyright (c) SimpleStaking and Tezedge Contributors
 // SPDX-License-Identifier: MIT
 
-use tezos_encoding::encoding::{HasEncoding, Encoding, SchemaType, Field};
-use wireshark_epan_adapter::{FieldDescriptorOwned, FieldDescriptor, dissector::HasFields};
+use tezos_encoding::encoding::{HasEncoding, Encoding, SchemaType, TagMap};
+use std::{
+    iter,
+    rc::Rc,
+    cell::RefCell,
+    collections::{HashMap, HashSet, hash_map::Entry},
+};
+use wireshark_epan_adapter::{Abbrev, FieldDescriptorOwned, FieldDescriptor, dissector::HasFields};
 
 /// The wrapper around the type which has an encoding and a name as a static string.
 /// The wrapper needed because it is impossible to implement foreign trait for foreign type.
@@ -18,6 +24,46 @@ enum FieldKind {
     Nothing,
     String,
     IntDec,
+    Bool,
+}
+
+// the state of the registration of one type
+#[derive(Default)]
+struct Registration {
+    // the fields of the recursive encoding are registered once, relative to the outermost
+    // recursive value, the deeper values are shown using the same fields,
+    // see `DecodedValue::show`, there are no fields per level: `MAX_RECURSION_DEPTH` copies
+    // of the subtree would be too many, and one filter matches the value at any depth,
+    // the depth is seen by the nesting in the tree;
+    // this is the path of the outermost recursive value, if inside of it
+    base: Option<String>,
+    registered: HashSet<String>,
+    // the ids of each tag map, keyed by its address
+    tag_ids: HashMap<*const TagMap, Vec<u16>>,
+    // the encodings made during the registration live until its end,
+    // so the address of a tag map is never reused by another one
+    encodings: Vec<Rc<Encoding>>,
+}
+
+impl Registration {
+    // `TagMap` of `tezos_encoding` can only find the tag by its id or by its variant,
+    // it cannot list them, so the ids are probed, 256 or 65536 of them, once for each map
+    fn tag_ids(&mut self, size: usize, map: &TagMap) -> Vec<u16> {
+        self.tag_ids
+            .entry(map as *const TagMap)
+            .or_insert_with(|| {
+                (0..=(((1usize << (size * 8)) - 1) as u16))
+                    .filter(|&id| map.find_by_id(id).is_some())
+                    .collect()
+            })
+            .clone()
+    }
+
+    fn keep(&mut self, encoding: Encoding) -> Rc<Encoding> {
+        let encoding = Rc::new(encoding);
+        self.encodings.push(encoding.clone());
+        encoding
+    }
 }
 
 /// Create `FieldDescriptorOwned` the structure of wireshark-epan-adapter
@@ -28,8 +74,8 @@ fn to_descriptor(base: &str, this: &str, kind: FieldKind) -> FieldDescriptorOwne
         let mut v = this.chars().collect::<Vec<_>>();
         v[0] = v[0].to_uppercase().next().unwrap();
         v.into_iter()
-            .map(|x| if x == '_' { ' ' } else { x })
-            .chain(std::iter::once('\0'))
+            .map(|x| if x == '_' || x == '.' { ' ' } else { x })
+            .chain(iter::once('\0'))
             .collect()
     };
 
@@ -40,6 +86,7 @@ fn to_descriptor(base: &str, this: &str, kind: FieldKind) -> FieldDescriptorOwne
         FieldKind::Nothing => FieldDescriptorOwned::Nothing { name, abbrev },
         FieldKind::String => FieldDescriptorOwned::String { name, abbrev },
         FieldKind::IntDec => FieldDescriptorOwned::Int64Dec { name, abbrev },
+        FieldKind::Bool => FieldDescriptorOwned::Bool { name, abbrev },
     }
 }
 
@@ -68,7 +115,12 @@ where
         //          abbrev: "tezos.metadata_message.private_node\u{0}",
         //      },
         //  ]
-        fn recursive(base: &str, name: &str, encoding: &Encoding) -> Vec<FieldDescriptorOwned> {
+        fn recursive(
+            base: &str,
+            name: &str,
+            encoding: &Encoding,
+            registration: &mut Registration,
+        ) -> Vec<FieldDescriptorOwned> {
             let new_base = format!("{}.{}", base, name);
             let (kind, more) = match encoding {
                 &Encoding::Unit => (None, Vec::new()),
@@ -84,44 +136,66 @@ where
                 &Encoding::Z | &Encoding::Mutez => (Some(FieldKind::String), Vec::new()),
                 &Encoding::Float | &Encoding::RangedFloat => unimplemented!(),
                 &Encoding::Bool => (Some(FieldKind::String), Vec::new()),
-                &Encoding::String | &Encoding::Bytes => (Some(FieldKind::String), Vec::new()),
-                &Encoding::Tags(ref size, ref map) => (
-                    Some(FieldKind::Nothing),
-                    // have to probe all ids...
-                    (0..=(((1usize << (size.clone() * 8)) - 1) as u16))
+                &Encoding::String => {
+                    // the length prefix is a field on its own
+                    let length = format!("{}.length", name);
+                    let length = to_descriptor(base, &length, FieldKind::IntDec);
+                    (Some(FieldKind::String), vec![length])
+                },
+                &Encoding::Bytes => (Some(FieldKind::String), Vec::new()),
+                &Encoding::Tags(ref size, ref map) => {
+                    // the id of the variant, and the bytes of the unknown variant
+                    let tag = to_descriptor(new_base.as_str(), "tag", FieldKind::IntDec);
+                    let unknown = to_descriptor(new_base.as_str(), "unknown", FieldKind::String);
+                    let variants = registration
+                        .tag_ids(size.clone(), map)
+                        .into_iter()
                         .filter_map(|id| map.find_by_id(id))
                         .map(|tag| {
-                            recursive(new_base.as_str(), tag.get_variant(), tag.get_encoding())
+                            let encoding = tag.get_encoding();
+                            recursive(new_base.as_str(), tag.get_variant(), encoding, registration)
                         })
-                        .flatten()
-                        .collect(),
-                ),
+                        .flatten();
+                    (
+                        Some(FieldKind::Nothing),
+                        vec![tag, unknown].into_iter().chain(variants).collect(),
+                    )
+                },
                 &Encoding::List(ref encoding) => {
                     // list of uint8 can be presented as hex, just like `Encoding::Bytes`
                     // `Encoding::List(Encoding::Uint8)` is the same as `Encoding::Bytes`
                     if let &Encoding::Uint8 = encoding.as_ref() {
                         (Some(FieldKind::String), Vec::new())
                     } else {
-                        (None, recursive(base, name, encoding))
+                        // the container of the items, with the number of items and the size
+                        let count = to_descriptor(new_base.as_str(), "count", FieldKind::IntDec);
+                        let size = to_descriptor(new_base.as_str(), "size", FieldKind::IntDec);
+                        let items = recursive(new_base.as_str(), "item", encoding, registration);
+                        (
+                            Some(FieldKind::Nothing),
+                            vec![count, size].into_iter().chain(items).collect(),
+                        )
                     }
                 },
                 &Encoding::Enum => (Some(FieldKind::String), Vec::new()),
                 &Encoding::Option(ref encoding) | &Encoding::OptionalField(ref encoding) => {
-                    (None, recursive(base, name, encoding))
+                    // the presence byte is a field on its own
+                    let present = format!("{}.present", name);
+                    let present = to_descriptor(base, &present, FieldKind::Bool);
+                    (
+                        None,
+                        iter::once(present)
+                            .chain(recursive(base, name, encoding, registration))
+                            .collect(),
+                    )
                 },
                 &Encoding::Obj(ref fields) => (
                     Some(FieldKind::Nothing),
                     fields
                         .iter()
                         .map(|field| {
-                            // make exception for this field
-                            // because it is impossible to traversal infinite tree
-                            let encoding = if field.get_name() == "operation_hashes_path" {
-                                Encoding::Obj(vec![Field::new("path_component", Encoding::String)])
-                            } else {
-                                field.get_encoding().clone()
-                            };
-                            recursive(new_base.as_str(), field.get_name(), &encoding)
+                            let encoding = field.get_encoding();
+                            recursive(new_base.as_str(), field.get_name(), encoding, registration)
                         })
                         .flatten()
                         .collect(),
@@ -132,28 +206,93 @@ where
                         .enumerate()
                         .map(|(i, encoding)| {
                             let n = format!("{}", i);
-                            recursive(new_base.as_str(), &n, encoding)
+                            recursive(new_base.as_str(), &n, encoding, registration)
                         })
                         .flatten()
                         .collect(),
                 ),
-                &Encoding::Dynamic(ref encoding) => (None, recursive(base, name, encoding)),
-                &Encoding::Sized(_, ref encoding) => (None, recursive(base, name, encoding)),
-                &Encoding::Greedy(ref encoding) => (None, recursive(base, name, encoding)),
+                &Encoding::Dynamic(ref encoding) => {
+                    // the length prefix is a field on its own
+                    let length = format!("{}.length", name);
+                    let length = to_descriptor(base, &length, FieldKind::IntDec);
+                    (
+                        None,
+                        iter::once(length)
+                            .chain(recursive(base, name, encoding, registration))
+                            .collect(),
+                    )
+                },
+                &Encoding::Sized(_, ref encoding) => {
+                    (None, recursive(base, name, encoding, registration))
+                },
+                &Encoding::Greedy(ref encoding) => {
+                    (None, recursive(base, name, encoding, registration))
+                },
                 &Encoding::Hash(_) => (Some(FieldKind::String), Vec::new()),
-                &Encoding::Split(ref f) => (None, recursive(base, name, &f(SchemaType::Binary))),
+                &Encoding::Split(ref f) => {
+                    let encoding = registration.keep(f(SchemaType::Binary));
+                    (None, recursive(base, name, &encoding, registration))
+                },
                 &Encoding::Timestamp => (Some(FieldKind::String), Vec::new()),
-                // it is impossible to traversal infinite tree,
-                // so it should be replaced by something else
-                // the only such situation is `operation_hashes_path`,
-                // it treated as special case, see above
-                &Encoding::Lazy(ref _f) => panic!("should workaround somehow infinite tree"),
+                // it is impossible to traverse the infinite tree,
+                // so the deeper values reuse the fields of the outermost one
+                &Encoding::Lazy(ref f) => {
+                    let base = registration.base.clone().unwrap_or_else(|| base.to_owned());
+                    if registration.registered.insert(format!("{}.{}", base, name)) {
+                        let outer = registration.base.replace(base.clone());
+                        let encoding = registration.keep(f());
+                        let fields = recursive(&base, name, &encoding, registration);
+                        registration.base = outer;
+                        (None, fields)
+                    } else {
+                        (None, Vec::new())
+                    }
+                },
             };
             kind.map(|kind| to_descriptor(base, name, kind))
                 .into_iter()
                 .chain(more)
                 .collect()
         }
-        recursive("tezos", T::NAME, &T::encoding())
+
+        thread_local! {
+            // the encoding never changes, so the fields are generated once for each type
+            static CACHE: RefCell<HashMap<&'static str, Vec<FieldDescriptorOwned>>> =
+                RefCell::new(HashMap::new());
+        }
+
+        CACHE.with(|cache| {
+            cache
+                .borrow_mut()
+                .entry(T::NAME)
+                .or_insert_with(|| {
+                    let mut registration = Registration::default();
+                    let encoding = T::encoding();
+                    unique(recursive("tezos", T::NAME, &encoding, &mut registration))
+                })
+                .clone()
+        })
     }
 }
+
+// identical subtrees produce the same field twice, the dissector finds the field
+// by its abbrev, so only the first field with the abbrev is kept
+fn unique(fields: Vec<FieldDescriptorOwned>) -> Vec<FieldDescriptorOwned> {
+    let mut unique = HashMap::new();
+    fields
+        .into_iter()
+        .filter(|field| match unique.entry(field.abbrev()) {
+            Entry::Occupied(e) => {
+                if e.get() != field {
+                    log::warn!("the field {:?} conflicts with {:?}", field, e.get());
+                }
+                debug_assert_eq!(e.get(), field, "different fields with the same abbrev");
+                false
+            },
+            Entry::Vacant(e) => {
+                e.insert(field.clone());
+                true
+            },
+        })
+        .collect()
+}
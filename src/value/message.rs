@@ -2,31 +2,60 @@
 // SPDX-License-Identifier: MIT
 
 use tezos_encoding::encoding::{Encoding, SchemaType};
-use wireshark_epan_adapter::dissector::{Tree, TreeLeaf};
 use bytes::Buf;
-use chrono::NaiveDateTime;
+use serde::{Serialize, Deserialize};
 use std::ops::Range;
 use failure::Fail;
 use bit_vec::BitVec;
-use crypto::hash::HashType;
-use crate::range_tool::intersect;
+use super::decoded::{DecodedValue, Value};
+
+/// How deep the lazy encoding might recurse, the operations path is at most 8 levels deep,
+/// but deeper paths are decoded to report them.
+pub const MAX_RECURSION_DEPTH: usize = 64;
+
+/// No Tezos message is that long, a longer declared length means the data is broken,
+/// waiting for it would stall the direction.
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
 
 pub trait HasBodyRange {
     fn body(&self) -> Range<usize>;
 }
 
-#[derive(Debug, Fail)]
+#[derive(Clone, Debug, Fail, Serialize, Deserialize)]
 pub enum DecodingError {
     #[fail(display = "Not enough bytes")]
     NotEnoughData,
     #[fail(display = "Tag size not supported")]
     TagSizeNotSupported,
-    #[fail(display = "Tag not found")]
-    TagNotFound,
     #[fail(display = "Unexpected option value")]
     UnexpectedOptionDiscriminant,
-    #[fail(display = "Path tag should be 0x00 or 0x0f or 0xf0")]
-    BadPathTag,
+    #[fail(display = "Recursion is deeper than {} levels", limit)]
+    RecursionLimit {
+        limit: usize,
+        /// Where the value too deep starts.
+        range: Range<usize>,
+    },
+    #[fail(
+        display = "Declared length {} exceeds available {} bytes",
+        declared, available
+    )]
+    DynamicLengthOverflow {
+        declared: usize,
+        available: usize,
+        /// The length prefix in the buffer.
+        range: Range<usize>,
+    },
+}
+
+impl DecodingError {
+    /// Where the error is in the buffer, if known.
+    pub fn range(&self) -> Option<Range<usize>> {
+        match self {
+            &DecodingError::DynamicLengthOverflow { ref range, .. } => Some(range.clone()),
+            &DecodingError::RecursionLimit { ref range, .. } => Some(range.clone()),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -36,6 +65,10 @@ where
 {
     data: &'a [u8],
     chunks: &'a [C],
+    // the data is limited by the length of the enclosing value, so no more data will come
+    bounded: bool,
+    // how many lazy encodings are being decoded
+    depth: usize,
 }
 
 #[derive(Clone, Debug)]
@@ -44,18 +77,31 @@ pub struct ChunkedDataOffset {
     pub chunks_offset: usize,
 }
 
-impl ChunkedDataOffset {
-    pub fn following(&self, length: usize) -> Range<usize> {
-        self.data_offset..(self.data_offset + length)
-    }
-}
-
 impl<'a, C> ChunkedData<'a, C>
 where
     C: HasBodyRange,
 {
     pub fn new(data: &'a [u8], chunks: &'a [C]) -> Self {
-        ChunkedData { data, chunks }
+        ChunkedData {
+            data,
+            chunks,
+            bounded: false,
+            depth: 0,
+        }
+    }
+
+    /// No more data will come, the value longer than the data is an error.
+    pub fn complete(data: &'a [u8], chunks: &'a [C]) -> Self {
+        ChunkedData {
+            data,
+            chunks,
+            bounded: true,
+            depth: 0,
+        }
+    }
+
+    pub fn is_bounded(&self) -> bool {
+        self.bounded
     }
 
     fn limit(&self, offset: &ChunkedDataOffset, limit: usize) -> Result<Self, DecodingError> {
@@ -85,6 +131,8 @@ where
         Ok(ChunkedData {
             data: &self.data[..end],
             chunks: self.chunks,
+            bounded: true,
+            depth: self.depth,
         })
     }
 
@@ -99,6 +147,15 @@ where
     where
         F: FnOnce(&mut dyn Buf) -> T,
     {
+        // the previous cut might stop exactly at the end of the chunk
+        let range = self.chunks[offset.chunks_offset].body();
+        if offset.data_offset == range.end
+            && length > 0
+            && self.chunks.len() > offset.chunks_offset + 1
+        {
+            offset.chunks_offset += 1;
+            offset.data_offset = self.chunks[offset.chunks_offset].body().start;
+        }
         let range = self.chunks[offset.chunks_offset].body();
         assert!(
             range.contains(&offset.data_offset) || (offset.data_offset == range.end && length == 0)
@@ -149,6 +206,29 @@ where
         self.available(offset) == 0
     }
 
+    // bytes left in the run of chunks which holds the current message, if the run is known,
+    // the sender splits the message in chunks of equal size and only the last one is shorter,
+    // so the chunk of different size ends the run, and the first message is a single chunk
+    fn framed(&self, offset: &ChunkedDataOffset) -> Option<usize> {
+        let chunks = &self.chunks[offset.chunks_offset..];
+        let size = |c: &C| c.body().len();
+        let first = size(&chunks[0]);
+        let end = match offset.chunks_offset {
+            0 => 1,
+            _ => match chunks.iter().position(|c| size(c) != first)? {
+                // the current chunk is the last of the run
+                end if size(&chunks[end]) > first => end,
+                end => end + 1,
+            },
+        };
+        let run = &chunks[..end];
+        if run.iter().any(|c| self.data.len() < c.body().end) {
+            return None;
+        }
+        let passed = offset.data_offset - chunks[0].body().start;
+        Some(run.iter().map(size).sum::<usize>() - passed)
+    }
+
     fn available(&self, offset: &ChunkedDataOffset) -> usize {
         let end = usize::min(
             self.chunks[offset.chunks_offset].body().end,
@@ -250,329 +330,289 @@ where
         Ok(str_num)
     }
 
-    pub fn read_path(
+    // creates the node, its children are in the `f`,
+    // the node is created even if decoding of children fails, so partial result is visible
+    fn node<F>(
         &self,
         offset: &mut ChunkedDataOffset,
-        v: &mut Vec<String>,
-    ) -> Result<(), DecodingError> {
-        match self.cut(offset, 1, |b| b.get_u8())? {
-            0x00 => Ok(()),
-            0xf0 => {
-                self.read_path(offset, v)?;
-                let l = HashType::OperationListListHash.size();
-                let hash = self.cut(offset, l, |b| hex::encode(b.bytes()))?;
-                v.push(format!("left: {}", hash));
-                Ok(())
-            },
-            0x0f => {
-                let l = HashType::OperationListListHash.size();
-                let hash = self.cut(offset, l, |b| hex::encode(b.bytes()))?;
-                self.read_path(offset, v)?;
-                v.push(format!("right: {}", hash));
-                Ok(())
-            },
-            _ => Err(DecodingError::BadPathTag),
-        }
+        name: &str,
+        out: &mut Vec<DecodedValue>,
+        f: F,
+    ) -> Result<(), DecodingError>
+    where
+        F: FnOnce(&mut ChunkedDataOffset, &mut Vec<DecodedValue>) -> Result<(), DecodingError>,
+    {
+        let start = offset.data_offset;
+        self.node_at(start, offset, name, out, f)
+    }
+
+    // the same, but the node starts before the offset, e.g. at the tag id
+    fn node_at<F>(
+        &self,
+        start: usize,
+        offset: &mut ChunkedDataOffset,
+        name: &str,
+        out: &mut Vec<DecodedValue>,
+        f: F,
+    ) -> Result<(), DecodingError>
+    where
+        F: FnOnce(&mut ChunkedDataOffset, &mut Vec<DecodedValue>) -> Result<(), DecodingError>,
+    {
+        let mut children = Vec::new();
+        let result = f(offset, &mut children);
+        out.push(DecodedValue::new(
+            name,
+            start..offset.data_offset,
+            Value::Node(children),
+        ));
+        result
+    }
+
+    // the same as `node`, but the children are items of the list
+    fn list<F>(
+        &self,
+        offset: &mut ChunkedDataOffset,
+        name: &str,
+        out: &mut Vec<DecodedValue>,
+        f: F,
+    ) -> Result<(), DecodingError>
+    where
+        F: FnOnce(&mut ChunkedDataOffset, &mut Vec<DecodedValue>) -> Result<(), DecodingError>,
+    {
+        let start = offset.data_offset;
+        let mut items = Vec::new();
+        let result = f(offset, &mut items);
+        out.push(DecodedValue::new(
+            name,
+            start..offset.data_offset,
+            Value::List(items),
+        ));
+        result
+    }
+
+    fn leaf<F>(
+        &self,
+        offset: &mut ChunkedDataOffset,
+        name: &str,
+        out: &mut Vec<DecodedValue>,
+        f: F,
+    ) -> Result<(), DecodingError>
+    where
+        F: FnOnce(&mut ChunkedDataOffset) -> Result<Value, DecodingError>,
+    {
+        let start = offset.data_offset;
+        let value = f(offset)?;
+        out.push(DecodedValue::new(name, start..offset.data_offset, value));
+        Ok(())
     }
 
-    pub fn show(
+    /// Decode the data and append the result in `out`.
+    /// The list appends each item, the option appends its presence and then the value if any.
+    pub fn decode(
         &self,
         offset: &mut ChunkedDataOffset,
         encoding: &Encoding,
-        space: &Range<usize>,
-        base: &str,
-        node: &mut Tree,
+        name: &str,
+        out: &mut Vec<DecodedValue>,
     ) -> Result<(), DecodingError> {
         match encoding {
             &Encoding::Unit => (),
-            &Encoding::Int8 => {
-                let item = offset.following(1);
-                let value = self.cut(offset, item.len(), |b| b.get_i8())?;
-                node.add(base, intersect(space, item), TreeLeaf::dec(value as _));
-            },
-            &Encoding::Uint8 => {
-                let item = offset.following(1);
-                let value = self.cut(offset, item.len(), |b| b.get_u8())?;
-                node.add(base, intersect(space, item), TreeLeaf::dec(value as _));
-            },
-            &Encoding::Int16 => {
-                let item = offset.following(2);
-                let value = self.cut(offset, item.len(), |b| b.get_i16())?;
-                node.add(base, intersect(space, item), TreeLeaf::dec(value as _));
-            },
-            &Encoding::Uint16 => {
-                let item = offset.following(2);
-                let value = self.cut(offset, item.len(), |b| b.get_u16())?;
-                node.add(base, intersect(space, item), TreeLeaf::dec(value as _));
-            },
-            &Encoding::Int31 | &Encoding::Int32 => {
-                let item = offset.following(4);
-                let value = self.cut(offset, item.len(), |b| b.get_i32())?;
-                node.add(base, intersect(space, item), TreeLeaf::dec(value as _));
-            },
-            &Encoding::Uint32 => {
-                let item = offset.following(4);
-                let value = self.cut(offset, item.len(), |b| b.get_u32())?;
-                node.add(base, intersect(space, item), TreeLeaf::dec(value.into()));
-            },
-            &Encoding::Int64 => {
-                let item = offset.following(8);
-                let value = self.cut(offset, item.len(), |b| b.get_i64())?;
-                node.add(base, intersect(space, item), TreeLeaf::dec(value as _));
-            },
+            &Encoding::Int8 => self.leaf(offset, name, out, |offset| {
+                self.cut(offset, 1, |b| Value::Int(b.get_i8() as _))
+            })?,
+            &Encoding::Uint8 => self.leaf(offset, name, out, |offset| {
+                self.cut(offset, 1, |b| Value::Int(b.get_u8() as _))
+            })?,
+            &Encoding::Int16 => self.leaf(offset, name, out, |offset| {
+                self.cut(offset, 2, |b| Value::Int(b.get_i16() as _))
+            })?,
+            &Encoding::Uint16 => self.leaf(offset, name, out, |offset| {
+                self.cut(offset, 2, |b| Value::Int(b.get_u16() as _))
+            })?,
+            &Encoding::Int31 | &Encoding::Int32 => self.leaf(offset, name, out, |offset| {
+                self.cut(offset, 4, |b| Value::Int(b.get_i32() as _))
+            })?,
+            &Encoding::Uint32 => self.leaf(offset, name, out, |offset| {
+                self.cut(offset, 4, |b| Value::Int(b.get_u32().into()))
+            })?,
+            &Encoding::Int64 => self.leaf(offset, name, out, |offset| {
+                self.cut(offset, 8, |b| Value::Int(b.get_i64()))
+            })?,
             &Encoding::RangedInt => unimplemented!(),
-            &Encoding::Z => {
-                let mut item = offset.following(0);
-                let value = self.read_z(offset)?;
-                item.end = offset.data_offset;
-                node.add(base, intersect(space, item), TreeLeaf::Display(value));
-            },
-            &Encoding::Mutez => {
-                let mut item = offset.following(0);
-                let value = self.read_mutez(offset)?;
-                item.end = offset.data_offset;
-                node.add(base, intersect(space, item), TreeLeaf::Display(value));
-            },
-            &Encoding::Float => {
-                let item = offset.following(8);
-                let value = self.cut(offset, item.len(), |b| b.get_f64())?;
-                node.add(base, intersect(space, item), TreeLeaf::float(value as _));
-            },
+            &Encoding::Z => self.leaf(offset, name, out, |offset| {
+                self.read_z(offset).map(Value::BigInt)
+            })?,
+            &Encoding::Mutez => self.leaf(offset, name, out, |offset| {
+                self.read_mutez(offset).map(Value::BigInt)
+            })?,
+            &Encoding::Float => self.leaf(offset, name, out, |offset| {
+                self.cut(offset, 8, |b| Value::Float(b.get_f64()))
+            })?,
             &Encoding::RangedFloat => unimplemented!(),
-            &Encoding::Bool => {
-                let item = offset.following(1);
-                let value = self.cut(offset, item.len(), |d| d.get_u8() == 0xff)?;
-                node.add(base, intersect(space, item), TreeLeaf::Display(value));
-            },
+            &Encoding::Bool => self.leaf(offset, name, out, |offset| {
+                self.cut(offset, 1, |d| Value::Bool(d.get_u8() == 0xff))
+            })?,
             &Encoding::String => {
-                let mut item = offset.following(4);
-                let length = self.cut(offset, item.len(), |b| b.get_u32())? as usize;
-                let f = |b: &mut dyn Buf| String::from_utf8((b.bytes()).to_owned()).ok();
-                let string = self.cut(offset, length, f)?;
-                item.end = offset.data_offset;
-                if let Some(s) = string {
-                    node.add(base, intersect(space, item), TreeLeaf::Display(s));
-                }
-            },
-            &Encoding::Bytes => {
-                let item = offset.following(self.available(offset));
-                let string = self.cut(offset, item.len(), |d| hex::encode(d.bytes()))?;
-                node.add(base, intersect(space, item), TreeLeaf::Display(string));
+                let start = offset.data_offset;
+                let length = self.cut(offset, 4, |b| b.get_u32())? as usize;
+                let length_name = format!("{}.length", name);
+                let value = Value::Int(length as _);
+                out.push(DecodedValue::new(
+                    &length_name,
+                    start..offset.data_offset,
+                    value,
+                ));
+                // invalid UTF-8 is shown in hex
+                self.leaf(offset, name, out, |offset| {
+                    self.cut(offset, length, |b| {
+                        match String::from_utf8(b.bytes().to_owned()) {
+                            Ok(s) => Value::String(s),
+                            Err(e) => Value::Bytes(e.into_bytes()),
+                        }
+                    })
+                })?
             },
+            &Encoding::Bytes => self.leaf(offset, name, out, |offset| {
+                let length = self.available(offset);
+                self.cut(offset, length, |d| Value::Bytes(d.bytes().to_vec()))
+            })?,
             &Encoding::Tags(ref tag_size, ref tag_map) => {
+                let start = offset.data_offset;
                 let id = match tag_size {
                     &1 => self.cut(offset, 1, |b| b.get_u8())? as u16,
                     &2 => self.cut(offset, 2, |b| b.get_u16())?,
                     _ => return Err(DecodingError::TagSizeNotSupported),
                 };
-                if let Some(tag) = tag_map.find_by_id(id) {
-                    let encoding = tag.get_encoding();
-                    let mut temp_offset = offset.clone();
-                    let size = self.estimate_size(&mut temp_offset, encoding)?;
-                    let item = offset.following(size);
-                    let range = intersect(space, item);
-                    let mut sub_node = node.add(base, range, TreeLeaf::nothing()).subtree();
-                    let variant = tag.get_variant();
-                    self.show(offset, encoding, space, variant, &mut sub_node)?;
-                } else {
-                    return Err(DecodingError::TagNotFound);
-                }
+                let tag = tag_map.find_by_id(id);
+                self.node_at(start, offset, name, out, |offset, children| {
+                    let variant = tag.map(|tag| tag.get_variant().to_owned());
+                    let value = Value::Tag { id, variant };
+                    children.push(DecodedValue::new("tag", start..offset.data_offset, value));
+                    match tag {
+                        Some(tag) => {
+                            self.decode(offset, tag.get_encoding(), tag.get_variant(), children)
+                        },
+                        // cannot know where the variant ends, the rest is opaque
+                        None => self.decode(offset, &Encoding::Bytes, "unknown", children),
+                    }
+                })?;
             },
             &Encoding::List(ref encoding) => {
                 if let &Encoding::Uint8 = encoding.as_ref() {
-                    self.show(offset, &Encoding::Bytes, space, base, node)?;
+                    self.decode(offset, &Encoding::Bytes, name, out)?;
                 } else {
-                    while !self.empty(offset) {
-                        self.show(offset, encoding, space, base, node)?;
-                    }
+                    self.list(offset, name, out, |offset, items| {
+                        while !self.empty(offset) {
+                            self.decode(offset, encoding, "item", items)?;
+                        }
+                        Ok(())
+                    })?;
                 }
             },
-            &Encoding::Enum => self.show(offset, &Encoding::Uint32, space, base, node)?,
+            &Encoding::Enum => self.decode(offset, &Encoding::Uint32, name, out)?,
             &Encoding::Option(ref encoding) | &Encoding::OptionalField(ref encoding) => {
-                match self.cut(offset, 1, |b| b.get_u8())? {
-                    0 => (),
-                    1 => self.show(offset, encoding, space, base, node)?,
+                let start = offset.data_offset;
+                let present = match self.cut(offset, 1, |b| b.get_u8())? {
+                    0 => false,
+                    1 => true,
                     _ => return Err(DecodingError::UnexpectedOptionDiscriminant),
+                };
+                // the presence byte is shown even if the value is absent
+                let presence_name = format!("{}.present", name);
+                let value = Value::Presence(present);
+                out.push(DecodedValue::new(
+                    &presence_name,
+                    start..offset.data_offset,
+                    value,
+                ));
+                if present {
+                    self.decode(offset, encoding, name, out)?;
                 }
             },
-            &Encoding::Obj(ref fields) => {
-                let mut temp_offset = offset.clone();
-                let size = self.estimate_size(&mut temp_offset, &Encoding::Obj(fields.clone()))?;
-                let item = offset.following(size);
-                let range = intersect(space, item);
-                let mut sub_node = node.add(base, range, TreeLeaf::nothing()).subtree();
+            &Encoding::Obj(ref fields) => self.node(offset, name, out, |offset, children| {
                 for field in fields {
-                    if field.get_name() == "operation_hashes_path" {
-                        let mut item = offset.following(0);
-                        let mut path = Vec::new();
-                        self.read_path(offset, &mut path)?;
-                        item.end = offset.data_offset;
-                        let range = intersect(space, item);
-                        let mut p = sub_node
-                            .add(field.get_name(), range, TreeLeaf::nothing())
-                            .subtree();
-                        for component in path.into_iter().rev() {
-                            p.add("path_component", 0..0, TreeLeaf::Display(component));
-                        }
-                    } else {
-                        self.show(
-                            offset,
-                            field.get_encoding(),
-                            space,
-                            field.get_name(),
-                            &mut sub_node,
-                        )?;
-                    }
+                    self.decode(offset, field.get_encoding(), field.get_name(), children)?;
                 }
-            },
-            &Encoding::Tup(ref encodings) => {
-                let mut temp_offset = offset.clone();
-                let size =
-                    self.estimate_size(&mut temp_offset, &Encoding::Tup(encodings.clone()))?;
-                let item = offset.following(size);
-                let range = intersect(space, item);
-                let mut sub_node = node.add(base, range, TreeLeaf::nothing()).subtree();
+                Ok(())
+            })?,
+            &Encoding::Tup(ref encodings) => self.node(offset, name, out, |offset, children| {
                 for (i, encoding) in encodings.iter().enumerate() {
                     let n = format!("{}", i);
-                    self.show(offset, encoding, space, &n, &mut sub_node)?;
+                    self.decode(offset, encoding, &n, children)?;
                 }
-            },
+                Ok(())
+            })?,
             &Encoding::Dynamic(ref encoding) => {
-                // TODO: use item, highlight the length
-                let item = offset.following(4);
-                let length = self.cut(offset, item.len(), |b| b.get_u32())? as usize;
-                if length <= self.available(offset) {
+                let start = offset.data_offset;
+                let length = self.cut(offset, 4, |b| b.get_u32())? as usize;
+                let range = start..offset.data_offset;
+                let length_name = format!("{}.length", name);
+                let value = Value::Int(length as _);
+                out.push(DecodedValue::new(&length_name, range.clone(), value));
+                let available = self.available(offset);
+                if length <= available {
                     self.limit(offset, length)?
-                        .show(offset, encoding, space, base, node)?;
+                        .decode(offset, encoding, name, out)?;
+                } else if self.bounded || length > MAX_MESSAGE_SIZE {
+                    return Err(DecodingError::DynamicLengthOverflow {
+                        declared: length,
+                        available,
+                        range,
+                    });
+                } else if let Some(framed) = self.framed(offset) {
+                    // the run of chunks is over, waiting would hide the next messages
+                    return Err(DecodingError::DynamicLengthOverflow {
+                        declared: length,
+                        available: framed,
+                        range,
+                    });
                 } else {
-                    // report error
+                    // the rest of the message is in chunks not yet received
+                    return Err(DecodingError::NotEnoughData);
                 }
             },
             &Encoding::Sized(ref size, ref encoding) => {
                 self.limit(offset, size.clone())?
-                    .show(offset, encoding, space, base, node)?;
+                    .decode(offset, encoding, name, out)?;
             },
             &Encoding::Greedy(ref encoding) => {
-                self.show(offset, encoding, space, base, node)?;
-            },
-            &Encoding::Hash(ref hash_type) => {
-                let item = offset.following(hash_type.size());
-                let string = self.cut(offset, item.len(), |d| hex::encode(d.bytes()))?;
-                node.add(base, intersect(space, item), TreeLeaf::Display(string));
+                self.decode(offset, encoding, name, out)?;
             },
+            &Encoding::Hash(ref hash_type) => self.leaf(offset, name, out, |offset| {
+                self.cut(offset, hash_type.size(), |d| {
+                    Value::Hash(hash_type.bytes_to_string(d.bytes()))
+                })
+            })?,
             &Encoding::Split(ref f) => {
-                self.show(offset, &f(SchemaType::Binary), space, base, node)?;
-            },
-            &Encoding::Timestamp => {
-                let item = offset.following(8);
-                let value = self.cut(offset, item.len(), |b| b.get_i64())?;
-                let time = NaiveDateTime::from_timestamp(value, 0);
-                node.add(base, intersect(space, item), TreeLeaf::Display(time));
-            },
-            &Encoding::Lazy(ref _f) => {
-                panic!("should not happen");
-            },
-        };
-        Ok(())
-    }
-
-    // TODO: it is double work, optimize it out
-    // we should store decoded data and show it only when whole node is collected
-    pub fn estimate_size(
-        &self,
-        offset: &mut ChunkedDataOffset,
-        encoding: &Encoding,
-    ) -> Result<usize, DecodingError> {
-        match encoding {
-            &Encoding::Unit => Ok(0),
-            &Encoding::Int8 | &Encoding::Uint8 => self.cut(offset, 1, |a| a.bytes().len()),
-            &Encoding::Int16 | &Encoding::Uint16 => self.cut(offset, 2, |a| a.bytes().len()),
-            &Encoding::Int31 | &Encoding::Int32 | &Encoding::Uint32 => {
-                self.cut(offset, 4, |a| a.bytes().len())
-            },
-            &Encoding::Int64 => self.cut(offset, 8, |a| a.bytes().len()),
-            &Encoding::RangedInt => unimplemented!(),
-            &Encoding::Z => {
-                let start = offset.data_offset;
-                let _ = self.read_z(offset)?;
-                Ok(offset.data_offset - start)
+                self.decode(offset, &f(SchemaType::Binary), name, out)?;
             },
-            &Encoding::Mutez => {
+            &Encoding::Timestamp => self.leaf(offset, name, out, |offset| {
+                self.cut(offset, 8, |b| Value::Timestamp(b.get_i64()))
+            })?,
+            &Encoding::Lazy(ref f) => {
                 let start = offset.data_offset;
-                let _ = self.read_mutez(offset)?;
-                Ok(offset.data_offset - start)
-            },
-            &Encoding::Float => self.cut(offset, 8, |a| a.bytes().len()),
-            &Encoding::RangedFloat => unimplemented!(),
-            &Encoding::Bool => self.cut(offset, 1, |a| a.bytes().len()),
-            &Encoding::String => {
-                let l = self.cut(offset, 4, |b| b.get_u32())? as usize;
-                self.cut(offset, l, |a| a.bytes().len() + 4)
-            },
-            &Encoding::Bytes => {
-                let l = self.available(offset);
-                self.cut(offset, l, |a| a.bytes().len())
-            },
-            &Encoding::Tags(ref tag_size, ref tag_map) => {
-                let id = match tag_size {
-                    &1 => self.cut(offset, 1, |b| b.get_u8())? as u16,
-                    &2 => self.cut(offset, 2, |b| b.get_u16())?,
-                    _ => {
-                        log::warn!("unsupported tag size");
-                        return Err(DecodingError::TagSizeNotSupported);
-                    },
-                };
-                if let Some(tag) = tag_map.find_by_id(id) {
-                    self.estimate_size(offset, tag.get_encoding())
-                        .map(|s| s + tag_size.clone())
-                } else {
-                    Err(DecodingError::TagNotFound)
-                }
-            },
-            &Encoding::List(_) => {
-                let l = self.available(offset);
-                self.cut(offset, l, |a| a.bytes().len())
-            },
-            &Encoding::Enum => self.estimate_size(offset, &Encoding::Uint32),
-            &Encoding::Option(ref encoding) | &Encoding::OptionalField(ref encoding) => {
-                match self.cut(offset, 1, |b| b.get_u8())? {
-                    0 => Ok(1),
-                    1 => self.estimate_size(offset, encoding).map(|s| s + 1),
-                    _ => Err(DecodingError::UnexpectedOptionDiscriminant),
+                if self.depth >= MAX_RECURSION_DEPTH {
+                    let end = start + usize::min(1, self.available(offset));
+                    return Err(DecodingError::RecursionLimit {
+                        limit: MAX_RECURSION_DEPTH,
+                        range: start..end,
+                    });
                 }
+                let inner = ChunkedData {
+                    data: self.data,
+                    chunks: self.chunks,
+                    bounded: self.bounded,
+                    depth: self.depth + 1,
+                };
+                let mut values = Vec::new();
+                let result = inner.decode(offset, &f(), name, &mut values);
+                // the values are visible even if decoding fails
+                let value = Value::Recursive(values);
+                out.push(DecodedValue::new(name, start..offset.data_offset, value));
+                result?
             },
-            &Encoding::Tup(ref encodings) => encodings
-                .iter()
-                .map(|e| self.estimate_size(offset, e))
-                .try_fold(0, |sum, size_at| size_at.map(|s| s + sum)),
-            &Encoding::Obj(ref fields) => fields
-                .iter()
-                .map(|f| {
-                    if f.get_name() == "operation_hashes_path" {
-                        let start = offset.data_offset;
-                        self.read_path(offset, &mut Vec::new())?;
-                        Ok(offset.data_offset - start)
-                    } else {
-                        self.estimate_size(offset, f.get_encoding())
-                    }
-                })
-                .try_fold(0, |sum, size_at_field| size_at_field.map(|s| s + sum)),
-            &Encoding::Dynamic(_) => {
-                let l = self.cut(offset, 4, |b| b.get_u32())? as usize;
-                self.cut(offset, l, |a| a.bytes().len() + 4)
-            },
-            &Encoding::Sized(ref size, _) => self.cut(offset, size.clone(), |a| a.bytes().len()),
-            &Encoding::Greedy(_) => {
-                let l = self.available(offset);
-                self.cut(offset, l, |a| a.bytes().len())
-            },
-            &Encoding::Hash(ref hash_type) => {
-                self.cut(offset, hash_type.size(), |a| a.bytes().len())
-            },
-            &Encoding::Timestamp => self.cut(offset, 8, |a| a.bytes().len()),
-            &Encoding::Split(ref f) => self.estimate_size(offset, &f(SchemaType::Binary)),
-            &Encoding::Lazy(ref _f) => panic!("should not happen"),
-        }
+        };
+        Ok(())
     }
 }
 
@@ -626,7 +666,17 @@ fn to_byte_vec(s: &BitVec) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use std::ops::Range;
-    use super::{ChunkedData, ChunkedDataOffset, HasBodyRange};
+    use tezos_encoding::encoding::{Encoding, HasEncoding};
+    use crypto::hash::HashType;
+    use wireshark_epan_adapter::dissector::{HasFields, Tree};
+    use crate::{
+        names::Names,
+        value::fields::{Named, TezosEncoded},
+    };
+    use super::{
+        ChunkedData, ChunkedDataOffset, HasBodyRange, DecodingError, MAX_RECURSION_DEPTH,
+        MAX_MESSAGE_SIZE,
+    };
 
     impl HasBodyRange for Range<usize> {
         fn body(&self) -> Range<usize> {
@@ -661,10 +711,7 @@ mod tests {
             },
         );
 
-        f(ChunkedData {
-            data: data.as_ref(),
-            chunks: chunks.as_ref(),
-        })
+        f(ChunkedData::new(data.as_ref(), chunks.as_ref()))
     }
 
     #[test]
@@ -689,4 +736,263 @@ mod tests {
             assert_eq!(cut, "bbbccccccccccccccccccccccccdddddddd");
         });
     }
+
+    #[test]
+    fn cut_at_chunk_boundary() {
+        let mut offset = ChunkedDataOffset {
+            chunks_offset: 0,
+            data_offset: 0,
+        };
+
+        with_test_data(|data| {
+            let cut = data
+                .cut(&mut offset, 12, |b| {
+                    String::from_utf8(b.to_bytes().to_vec()).unwrap()
+                })
+                .unwrap();
+            assert_eq!(cut, "aaaaaaaaaaaa");
+            let cut = data
+                .cut(&mut offset, 4, |b| {
+                    String::from_utf8(b.to_bytes().to_vec()).unwrap()
+                })
+                .unwrap();
+            assert_eq!(cut, "bbbb");
+            assert_eq!(offset.chunks_offset, 1);
+        });
+    }
+
+    #[test]
+    fn dynamic_length_overflow() {
+        // the outer value is 8 bytes, the inner declares 100 bytes
+        let data = [0, 0, 0, 8, 0, 0, 0, 100, 1, 2, 3, 4];
+        let chunks: Vec<Range<usize>> = vec![0..12];
+        let data = ChunkedData::new(data.as_ref(), chunks.as_ref());
+        let mut offset = ChunkedDataOffset {
+            chunks_offset: 0,
+            data_offset: 0,
+        };
+        let encoding = Encoding::Dynamic(Box::new(Encoding::Dynamic(Box::new(Encoding::Bytes))));
+        let mut out = Vec::new();
+        match data.decode(&mut offset, &encoding, "data", &mut out) {
+            Err(DecodingError::DynamicLengthOverflow {
+                declared,
+                available,
+                range,
+            }) => {
+                assert_eq!((declared, available, range), (100, 4, 4..8));
+            },
+            other => panic!("{:?}", other),
+        }
+        // both length prefixes are visible
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[1].range, 4..8);
+    }
+
+    #[test]
+    fn unbounded_dynamic_length() {
+        // the message starts at the second chunk, the first chunk is empty
+        let decode = |data: &[u8], chunks: Vec<Range<usize>>| {
+            let data = ChunkedData::new(data, chunks.as_ref());
+            let mut offset = ChunkedDataOffset {
+                chunks_offset: 1,
+                data_offset: 0,
+            };
+            let encoding = Encoding::Dynamic(Box::new(Encoding::Bytes));
+            data.decode(&mut offset, &encoding, "data", &mut Vec::new())
+        };
+        // the rest might come in the next chunks
+        match decode(&[0, 0, 0, 100, 1, 2], vec![0..0, 0..6]) {
+            Err(DecodingError::NotEnoughData) => (),
+            other => panic!("{:?}", other),
+        }
+        // the shorter chunk ends the message, the rest will not come
+        match decode(&[0, 0, 0, 100, 1, 2, 3, 4], vec![0..0, 0..6, 6..8]) {
+            Err(DecodingError::DynamicLengthOverflow {
+                declared: 100,
+                available: 4,
+                ..
+            }) => (),
+            other => panic!("{:?}", other),
+        }
+        // but not that much
+        match decode(&[0xff, 0xff, 0xff, 0xff, 1, 2], vec![0..0, 0..6]) {
+            Err(DecodingError::DynamicLengthOverflow { declared, .. }) => {
+                assert_eq!(declared, 0xffffffff);
+                assert!(declared > MAX_MESSAGE_SIZE);
+            },
+            other => panic!("{:?}", other),
+        }
+    }
+
+    // the path in the tree of operation lists, `0xf0` left, `0x0f` right, `0x00` end
+    fn path_encoding() -> Encoding {
+        use std::sync::Arc;
+        use tezos_encoding::encoding::{Field, Tag, TagMap};
+
+        let lazy = || Encoding::Lazy(Arc::new(path_encoding));
+        let hash = || Encoding::Hash(HashType::OperationListListHash);
+        Encoding::Tags(
+            1,
+            TagMap::new(&[
+                Tag::new(
+                    0xf0,
+                    "Left",
+                    Encoding::Obj(vec![
+                        Field::new("path", lazy()),
+                        Field::new("right", hash()),
+                    ]),
+                ),
+                Tag::new(
+                    0x0f,
+                    "Right",
+                    Encoding::Obj(vec![Field::new("left", hash()), Field::new("path", lazy())]),
+                ),
+                Tag::new(0x00, "Op", Encoding::Unit),
+            ]),
+        )
+    }
+
+    #[test]
+    fn lazy_path() {
+        // right, the hash, end
+        let mut data = vec![0x0f];
+        data.extend_from_slice(&[0xab; 32]);
+        data.push(0x00);
+        let chunks: Vec<Range<usize>> = vec![0..data.len()];
+        let data = ChunkedData::new(data.as_ref(), chunks.as_ref());
+        let mut offset = ChunkedDataOffset {
+            chunks_offset: 0,
+            data_offset: 0,
+        };
+        let mut out = Vec::new();
+        data.decode(&mut offset, &path_encoding(), "path", &mut out)
+            .unwrap();
+        let right = &out[0].children()[1];
+        assert_eq!(right.children()[0].name, "left");
+        assert_eq!(right.children()[0].range, 1..33);
+        let path = &right.children()[1];
+        assert_eq!((path.name.as_str(), path.range.clone()), ("path", 33..34));
+        // the deeper path is the tagged union too
+        assert_eq!(path.children()[0].children()[0].name, "tag");
+    }
+
+    struct Path;
+
+    impl HasEncoding for Path {
+        fn encoding() -> Encoding {
+            path_encoding()
+        }
+    }
+
+    impl Named for Path {
+        const NAME: &'static str = "path";
+    }
+
+    #[test]
+    fn lazy_path_fields() {
+        // left, right, left, end, each level is shown on the fields of the outermost path
+        let mut data = vec![0xf0, 0x0f];
+        data.extend_from_slice(&[0xab; 32]);
+        data.extend_from_slice(&[0xf0, 0x00]);
+        data.extend_from_slice(&[0xcd; 32]);
+        data.extend_from_slice(&[0xef; 32]);
+        let chunks: Vec<Range<usize>> = vec![0..data.len()];
+        let chunked = ChunkedData::new(data.as_ref(), chunks.as_ref());
+        let mut offset = ChunkedDataOffset {
+            chunks_offset: 0,
+            data_offset: 0,
+        };
+        let mut out = Vec::new();
+        chunked
+            .decode(&mut offset, &path_encoding(), "path", &mut out)
+            .unwrap();
+
+        // the recorder panics if the path of some item is not registered
+        let mut node = Tree::recorder("tezos", &TezosEncoded::<Path>::fields());
+        for value in &out {
+            value.show(&(0..data.len()), &mut node, true, &Names::default());
+        }
+        let records = node.records();
+        let tags = records
+            .iter()
+            .filter(|r| r.path.ends_with(".tag"))
+            .map(|r| r.path.as_str())
+            .collect::<Vec<_>>();
+        let inner = "tezos.path.Left.path.tag";
+        assert_eq!(tags, ["tezos.path.tag", inner, inner, inner]);
+        let last = records.last().unwrap();
+        assert_eq!(last.path, "tezos.path.Left.right");
+        assert_eq!(last.range, 68..100);
+    }
+
+    #[test]
+    fn recursion_limit() {
+        let data = vec![0x0f; 1 + (MAX_RECURSION_DEPTH + 1) * 33];
+        let chunks: Vec<Range<usize>> = vec![0..data.len()];
+        let data = ChunkedData::new(data.as_ref(), chunks.as_ref());
+        let mut offset = ChunkedDataOffset {
+            chunks_offset: 0,
+            data_offset: 0,
+        };
+        let mut out = Vec::new();
+        match data.decode(&mut offset, &path_encoding(), "path", &mut out) {
+            Err(DecodingError::RecursionLimit { limit, .. }) => {
+                assert_eq!(limit, MAX_RECURSION_DEPTH)
+            },
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn option_presence() {
+        let data = [0, 1, 0, 7];
+        let chunks: Vec<Range<usize>> = vec![0..4];
+        let data = ChunkedData::new(data.as_ref(), chunks.as_ref());
+        let mut offset = ChunkedDataOffset {
+            chunks_offset: 0,
+            data_offset: 0,
+        };
+        let encoding = Encoding::Option(Box::new(Encoding::Uint16));
+        let mut out = Vec::new();
+        data.decode(&mut offset, &encoding, "a", &mut out).unwrap();
+        data.decode(&mut offset, &encoding, "b", &mut out).unwrap();
+        let values = out
+            .iter()
+            .map(|v| (v.name.as_str(), v.range.clone(), v.value.to_string()))
+            .collect::<Vec<_>>();
+        let expected = vec![
+            ("a.present", 0..1, "absent".to_owned()),
+            ("b.present", 1..2, "present".to_owned()),
+            ("b", 2..4, "7".to_owned()),
+        ];
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn unknown_tag() {
+        use tezos_encoding::encoding::{Tag, TagMap};
+
+        let data = [0, 2, 0xaa, 0xbb];
+        let chunks: Vec<Range<usize>> = vec![0..4];
+        let data = ChunkedData::new(data.as_ref(), chunks.as_ref());
+        let mut offset = ChunkedDataOffset {
+            chunks_offset: 0,
+            data_offset: 0,
+        };
+        let map = TagMap::new(&[Tag::new(1, "Known", Encoding::Unit)]);
+        let encoding = Encoding::Tags(2, map);
+        let mut out = Vec::new();
+        data.decode(&mut offset, &encoding, "message", &mut out)
+            .unwrap();
+        let values = out[0]
+            .children()
+            .iter()
+            .map(|v| (v.name.as_str(), v.range.clone(), v.value.to_string()))
+            .collect::<Vec<_>>();
+        let expected = vec![
+            ("tag", 0..2, "unknown tag 0x0002".to_owned()),
+            ("unknown", 2..4, "aabb".to_owned()),
+        ];
+        assert_eq!(values, expected);
+    }
 }
@@ -4,6 +4,11 @@
 mod fields;
 mod message;
 mod named;
+mod decoded;
+mod conformance;
 
-pub use self::fields::{TezosEncoded, Named};
-pub use self::message::{ChunkedData, ChunkedDataOffset, DecodingError, HasBodyRange};
+pub use self::fields::TezosEncoded;
+pub use self::message::HasBodyRange;
+pub use self::named::is_variant;
+pub use self::decoded::{DecodedMessage, DecodedValue, Value};
+pub use self::conformance::{Violation, MAX_CHUNK_CONTENT};
@@ -25,11 +25,24 @@ impl Named for PeerMessageResponse {
     const NAME: &'static str = "peer_message";
 }
 
+/// The variants of the tags are named like `CurrentHead`, the fields like `current_head`,
+/// compare the names ignoring the case and underscores.
+pub fn is_variant(name: &str, variant: &str) -> bool {
+    let simplify = |s: &str| {
+        s.chars()
+            .filter(|&c| c != '_')
+            .map(|c| c.to_ascii_lowercase())
+            .collect::<String>()
+    };
+    simplify(name) == simplify(variant)
+}
+
 #[cfg(test)]
 mod tests {
-    use wireshark_epan_adapter::dissector::HasFields;
+    use wireshark_epan_adapter::{Abbrev, dissector::HasFields};
     use super::{ConnectionMessage, AckMessage, MetadataMessage, PeerMessageResponse};
     use crate::value::TezosEncoded;
+    use std::collections::HashSet;
 
     #[test]
     fn connection_message_fields() {
@@ -54,4 +67,25 @@ mod tests {
         let fields = TezosEncoded::<PeerMessageResponse>::fields();
         println!("{:#?}", fields);
     }
+
+    // the plugin registers these types when Wireshark starts, each field once
+    #[test]
+    fn unique_fields() {
+        let fields = TezosEncoded::<ConnectionMessage>::fields()
+            .into_iter()
+            .chain(TezosEncoded::<MetadataMessage>::fields())
+            .chain(TezosEncoded::<AckMessage>::fields())
+            .chain(TezosEncoded::<PeerMessageResponse>::fields())
+            .collect::<Vec<_>>();
+        let abbrevs = fields.iter().map(Abbrev::abbrev).collect::<HashSet<_>>();
+        assert_eq!(abbrevs.len(), fields.len());
+    }
+
+    // the filter given as the example in the readme
+    #[test]
+    fn documented_field() {
+        let abbrev = "tezos.peer_message.messages.item.GetBlockHeaders.get_block_headers.count\0";
+        let fields = TezosEncoded::<PeerMessageResponse>::fields();
+        assert!(fields.iter().any(|field| field.abbrev() == abbrev));
+    }
 }
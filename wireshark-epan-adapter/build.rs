@@ -34,6 +34,8 @@ fn main() {
         .header(format!("{}/epan/dissectors/packet-tcp.h", base))
         .header(format!("{}/epan/wmem/wmem_user_cb.h", base))
         .header(format!("{}/epan/prefs.h", base))
+        .header(format!("{}/epan/uat.h", base))
+        .header(format!("{}/epan/expert.h", base))
         .generate()
         .expect("Unable to generate bindings");
 
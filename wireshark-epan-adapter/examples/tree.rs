@@ -29,6 +29,8 @@ extern "C" fn plugin_register() {
         DissectorDescriptor {
             display_name: "Simple\0",
             short_name: "simple_tcp\0",
+            // no default ports, the dissector still available in "Decode As..."
+            tcp_ports: "\0",
         },
         // name of protocol and filter
         NameDescriptor {
@@ -1,4 +1,4 @@
-/// Provides information about the packet: source/destination, if it already visited and number.
+/// Provides information about the packet: source/destination, timestamps, TCP stream and more.
 mod packet_info;
 pub use self::packet_info::{SocketAddress, PacketInfo};
 
@@ -8,4 +8,4 @@ pub use self::helper::{SuperDissectorData, DissectorHelper};
 
 /// Provides API for displaying data on tree UI.
 mod tree;
-pub use self::tree::{Tree, TreeLeaf, TreeMessage, TreeMessageMapItem, HasFields};
+pub use self::tree::{Tree, TreeLeaf, TreeRecord, TreeMessage, TreeMessageMapItem, HasFields};
@@ -1,7 +1,10 @@
 use std::{
     fmt,
     net::{SocketAddr, IpAddr},
+    time::Duration,
+    ptr,
 };
+use super::SuperDissectorData;
 use crate::sys;
 
 /// The most common socket address is ip (v4 or v6 and port),
@@ -32,11 +35,34 @@ impl fmt::Display for SocketAddress {
 /// Provides information about the packet.
 pub struct PacketInfo {
     inner: *mut sys::packet_info,
+    tcp: *mut sys::tcpinfo,
+    payload_offset: usize,
 }
 
 impl PacketInfo {
     pub(crate) fn new(raw: *mut sys::packet_info) -> Self {
-        PacketInfo { inner: raw }
+        PacketInfo {
+            inner: raw,
+            tcp: ptr::null_mut(),
+            payload_offset: 0,
+        }
+    }
+
+    /// Also knows about the transport, TCP provides its `tcpinfo` as the dissector data,
+    /// and about the payload `tvb` the dissector is called with.
+    pub(crate) fn with_data(
+        raw: *mut sys::packet_info,
+        data: &SuperDissectorData,
+        tvb: *mut sys::tvbuff_t,
+    ) -> Self {
+        let payload_offset = unsafe { sys::tvb_raw_offset(tvb) } as usize;
+        match data {
+            &SuperDissectorData::Tcp(tcp) => PacketInfo {
+                inner: raw,
+                tcp,
+                payload_offset,
+            },
+        }
     }
 
     pub(crate) fn inner(&self) -> &sys::packet_info {
@@ -63,10 +89,109 @@ impl PacketInfo {
         self.fd().num as _
     }
 
+    /// Where the payload starts in its data source, either the frame or the reassembled data.
+    /// TCP calls the dissector several times in one frame if the reassembled data ends
+    /// in the middle of the segment, each call has its own offset.
+    pub fn payload_offset(&self) -> usize {
+        self.payload_offset
+    }
+
+    /// Absolute time when the frame was captured, since the UNIX epoch.
+    pub fn abs_ts(&self) -> Duration {
+        let ts = self.fd().abs_ts;
+        Duration::new(ts.secs as _, ts.nsecs as _)
+    }
+
+    /// Time since the first frame of the capture, negative if the frame was captured earlier.
+    pub fn rel_ts(&self) -> f64 {
+        let ts = self.inner().rel_ts;
+        ts.secs as f64 + ts.nsecs as f64 / 1_000_000_000.0
+    }
+
+    /// How many bytes of the frame are captured, might be less than the frame length.
+    pub fn capture_length(&self) -> usize {
+        self.fd().cap_len as _
+    }
+
+    /// The capture interface, if the capture file records it.
+    pub fn interface_id(&self) -> Option<u32> {
+        let rec = unsafe { self.inner().rec.as_ref()? };
+        if rec.presence_flags & sys::WTAP_HAS_INTERFACE_ID != 0 {
+            Some(unsafe { rec.rec_header.packet_header.interface_id })
+        } else {
+            None
+        }
+    }
+
+    /// Sequence number of the first byte of the payload.
+    pub fn tcp_seq(&self) -> Option<u32> {
+        self.tcpinfo().map(|tcp| tcp.seq)
+    }
+
+    /// The index of the TCP stream, the same as `tcp.stream` field.
+    pub fn tcp_stream(&self) -> Option<u32> {
+        self.tcp_analysis().map(|tcpd| tcpd.stream)
+    }
+
+    /// TCP analysis found the frame is a retransmission.
+    /// Always false if the analysis is disabled in TCP preferences.
+    pub fn retransmission(&self) -> bool {
+        let tcpd = match self.tcp_analysis() {
+            Some(tcpd) => tcpd,
+            None => return false,
+        };
+        let acked = unsafe {
+            sys::wmem_tree_lookup32(tcpd.acked_table, self.fd().num) as *const sys::tcp_acked
+        };
+        unsafe { acked.as_ref() }
+            .map(|acked| acked.flags & sys::TCP_A_RETRANSMISSION != 0)
+            .unwrap_or(false)
+    }
+
+    fn tcpinfo(&self) -> Option<&sys::tcpinfo> {
+        unsafe { self.tcp.as_ref() }
+    }
+
+    // only valid if the frame came from TCP, TCP has already created the conversation
+    fn tcp_analysis(&self) -> Option<&sys::tcp_analysis> {
+        self.tcpinfo()?;
+        let pinfo = self.inner;
+        unsafe {
+            let conversation = sys::find_conversation_pinfo(pinfo, 0);
+            if conversation.is_null() {
+                return None;
+            }
+            sys::get_tcp_conversation_data(conversation, pinfo).as_ref()
+        }
+    }
+
     /// Is this packet was already processed by this dissector.
     pub fn visited(&self) -> bool {
         self.fd().visited() != 0
     }
+
+    /// Whether the dissector below, TCP, can reassemble the data for this dissector.
+    /// The user might disable it in TCP preferences.
+    pub fn can_desegment(&self) -> bool {
+        self.inner().can_desegment != 0
+    }
+
+    /// Ask TCP to deliver the data starting at `offset` of this frame again,
+    /// together with `length` more bytes, or with one more segment if `None`.
+    pub fn desegment(&self, offset: usize, length: Option<usize>) {
+        let length = length
+            .map(|l| l as u32)
+            .unwrap_or(sys::DESEGMENT_ONE_MORE_SEGMENT);
+        unsafe {
+            (*self.inner).desegment_offset = offset as _;
+            (*self.inner).desegment_len = length as _;
+        }
+    }
+
+    /// The dissector asked for more data, see `desegment`.
+    pub fn desegment_requested(&self) -> bool {
+        self.inner().desegment_len != 0
+    }
 }
 
 fn read_address(addr: sys::address, port: u16) -> SocketAddress {
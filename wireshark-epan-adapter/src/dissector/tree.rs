@@ -1,11 +1,15 @@
-use std::{collections::HashMap, ops::Range, rc::Rc, cell::RefCell, fmt};
-use crate::plugin::{FieldDescriptor, FieldDescriptorOwned};
+use std::{collections::HashMap, ops::Range, rc::Rc, cell::RefCell, fmt, ptr};
+use crate::plugin::{FieldDescriptor, FieldDescriptorOwned, Abbrev};
 use crate::sys;
 
 struct Common {
     fields: HashMap<String, i32>,
+    experts: HashMap<String, *mut sys::expert_field>,
     ett: i32,
     tvb: *mut sys::tvbuff_t,
+    pinfo: *mut sys::packet_info,
+    // the tree made by `Tree::recorder` collects the items instead of calling wireshark
+    records: Option<Vec<TreeRecord>>,
 }
 
 pub struct Tree {
@@ -13,6 +17,21 @@ pub struct Tree {
     parent_path: Option<String>,
     base: usize,
     node: *mut sys::proto_tree,
+    // the index of the item in the records
+    record: Option<usize>,
+}
+
+/// The item added to the tree made by `Tree::recorder`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TreeRecord {
+    /// The field, or the name of the expert info.
+    pub path: String,
+    pub range: Range<usize>,
+    pub value: String,
+    /// Set by `set_text`.
+    pub text: Option<String>,
+    /// Set by `prepend_text`.
+    pub prefix: String,
 }
 
 pub enum TreeLeaf<D>
@@ -23,6 +42,9 @@ where
     Display(D),
     Int64Dec(i64),
     Float64(f64),
+    Bool(bool),
+    /// Should be used with `FieldDescriptor::FrameNumber`.
+    FrameNumber(u64),
 }
 
 impl TreeLeaf<String> {
@@ -37,31 +59,85 @@ impl TreeLeaf<String> {
     pub fn nothing() -> Self {
         TreeLeaf::Nothing
     }
+
+    pub fn bool(v: bool) -> Self {
+        TreeLeaf::Bool(v)
+    }
+
+    pub fn frame(v: u64) -> Self {
+        TreeLeaf::FrameNumber(v)
+    }
 }
 
 impl Tree {
     pub(crate) fn root(
         fields: HashMap<String, i32>,
+        experts: HashMap<String, *mut sys::expert_field>,
         ett: i32,
         tvb: *mut sys::tvbuff_t,
+        pinfo: *mut sys::packet_info,
         root: *mut sys::proto_tree,
     ) -> Self {
-        let common = Common { fields, ett, tvb };
+        let common = Common {
+            fields,
+            experts,
+            ett,
+            tvb,
+            pinfo,
+            records: None,
+        };
 
         Tree {
             common: Rc::new(RefCell::new(common)),
             parent_path: None,
             base: 0,
             node: root,
+            record: None,
+        }
+    }
+
+    /// The tree which does not need wireshark, it only collects the items, see `records`.
+    /// Like the real tree, it panics if the field of the item is not among `fields`.
+    pub fn recorder(root: &str, fields: &[FieldDescriptorOwned]) -> Self {
+        let common = Common {
+            fields: fields.iter().map(|f| (f.abbrev(), -1)).collect(),
+            experts: HashMap::new(),
+            ett: -1,
+            tvb: ptr::null_mut(),
+            pinfo: ptr::null_mut(),
+            records: Some(Vec::new()),
+        };
+
+        Tree {
+            common: Rc::new(RefCell::new(common)),
+            parent_path: Some(format!("{}\0", root)),
+            base: 0,
+            node: ptr::null_mut(),
+            record: None,
         }
     }
 
+    /// The items collected by the tree made by `recorder`, empty for the real tree.
+    pub fn records(&self) -> Vec<TreeRecord> {
+        self.common.borrow().records.clone().unwrap_or_default()
+    }
+
+    fn recording(&self) -> bool {
+        self.common.borrow().records.is_some()
+    }
+
     pub fn subtree(&mut self) -> Self {
+        let node = if self.recording() {
+            ptr::null_mut()
+        } else {
+            unsafe { sys::proto_item_add_subtree(self.node, self.common.borrow().ett) }
+        };
         Tree {
             common: self.common.clone(),
             parent_path: self.parent_path.clone(),
             base: self.base,
-            node: unsafe { sys::proto_item_add_subtree(self.node, self.common.borrow().ett) },
+            node,
+            record: self.record,
         }
     }
 
@@ -70,7 +146,7 @@ impl Tree {
         D: fmt::Display,
         P: AsRef<str>,
     {
-        if cfg!(debug_assertions) {
+        if cfg!(debug_assertions) && !self.recording() {
             let length = unsafe { sys::tvb_captured_length(self.common.borrow().tvb) } as usize;
             assert!(range.start <= length);
             assert!(range.end <= length);
@@ -82,6 +158,34 @@ impl Tree {
             format!("{}\0", path.as_ref())
         };
 
+        if self.recording() {
+            let mut common = self.common.borrow_mut();
+            assert!(common.fields.contains_key(&full_path), "{}", full_path);
+            let value = match v {
+                TreeLeaf::Nothing => String::new(),
+                TreeLeaf::Display(value) => value.to_string(),
+                TreeLeaf::Int64Dec(value) => value.to_string(),
+                TreeLeaf::Float64(value) => value.to_string(),
+                TreeLeaf::Bool(value) => value.to_string(),
+                TreeLeaf::FrameNumber(value) => value.to_string(),
+            };
+            let records = common.records.as_mut().unwrap();
+            records.push(TreeRecord {
+                path: full_path.trim_end_matches('\0').to_owned(),
+                range: range.clone(),
+                value,
+                text: None,
+                prefix: String::new(),
+            });
+            return Tree {
+                common: self.common.clone(),
+                parent_path: Some(full_path),
+                base: range.start,
+                node: ptr::null_mut(),
+                record: Some(records.len() - 1),
+            };
+        }
+
         let node = match v {
             TreeLeaf::Nothing => unsafe {
                 sys::proto_tree_add_item(
@@ -116,6 +220,26 @@ impl Tree {
                     value,
                 )
             },
+            TreeLeaf::Bool(value) => unsafe {
+                sys::proto_tree_add_boolean(
+                    self.node,
+                    self.common.borrow().fields[&full_path],
+                    self.common.borrow().tvb,
+                    range.start as _,
+                    range.len() as _,
+                    value as _,
+                )
+            },
+            TreeLeaf::FrameNumber(value) => unsafe {
+                sys::proto_tree_add_uint(
+                    self.node,
+                    self.common.borrow().fields[&full_path],
+                    self.common.borrow().tvb,
+                    range.start as _,
+                    range.len() as _,
+                    value as _,
+                )
+            },
             TreeLeaf::Float64(value) => {
                 let _ = value;
                 unimplemented!()
@@ -127,6 +251,97 @@ impl Tree {
             parent_path: Some(full_path),
             base: range.start,
             node,
+            record: None,
+        }
+    }
+
+    /// The path of the fields of this node's children, for example `"tezos.connection_msg"`.
+    pub fn path(&self) -> &str {
+        self.parent_path
+            .as_ref()
+            .map(|p| p.trim_end_matches('\0'))
+            .unwrap_or("")
+    }
+
+    /// The same node, but the fields of its children are relative to the `path`,
+    /// so recursive values might reuse the fields registered for the outer value.
+    pub fn rebased(&self, path: &str) -> Self {
+        Tree {
+            common: self.common.clone(),
+            parent_path: Some(format!("{}\0", path)),
+            base: self.base,
+            node: self.node,
+            record: self.record,
+        }
+    }
+
+    /// Replace the label of this node, the field and its value remain the same.
+    pub fn set_text<D>(&mut self, text: D)
+    where
+        D: fmt::Display,
+    {
+        if self.recording() {
+            self.update_record(|r| r.text = Some(text.to_string()));
+            return;
+        }
+        let text = format!("{}\0", text);
+        unsafe { sys::proto_item_set_text(self.node, "%s\0".as_ptr() as _, text.as_ptr()) };
+    }
+
+    /// Put the text before the label of this node, for example the index of a list item.
+    pub fn prepend_text<D>(&mut self, text: D)
+    where
+        D: fmt::Display,
+    {
+        if self.recording() {
+            self.update_record(|r| r.prefix.insert_str(0, &text.to_string()));
+            return;
+        }
+        let text = format!("{}\0", text);
+        unsafe { sys::proto_item_prepend_text(self.node, "%s\0".as_ptr() as _, text.as_ptr()) };
+    }
+
+    /// Attach the expert info to this node, the `name` is from `ExpertDescriptor`,
+    /// for example `"tezos.handshake.rejected"`, the message is shown instead of the summary.
+    pub fn expert<D>(&mut self, name: &str, range: Range<usize>, message: D)
+    where
+        D: fmt::Display,
+    {
+        if self.recording() {
+            let mut common = self.common.borrow_mut();
+            common.records.as_mut().unwrap().push(TreeRecord {
+                path: name.to_owned(),
+                range,
+                value: message.to_string(),
+                text: None,
+                prefix: String::new(),
+            });
+            return;
+        }
+        let common = self.common.borrow();
+        let expert = common.experts[&format!("{}\0", name)];
+        let message = format!("{}\0", message);
+        unsafe {
+            sys::proto_tree_add_expert_format(
+                self.node,
+                common.pinfo,
+                expert,
+                common.tvb,
+                range.start as _,
+                range.len() as _,
+                "%s\0".as_ptr() as _,
+                message.as_ptr(),
+            );
+        }
+    }
+
+    fn update_record<F>(&self, f: F)
+    where
+        F: FnOnce(&mut TreeRecord),
+    {
+        let mut common = self.common.borrow_mut();
+        if let (Some(index), Some(records)) = (self.record, common.records.as_mut()) {
+            f(&mut records[index])
         }
     }
 
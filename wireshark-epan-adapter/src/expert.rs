@@ -0,0 +1,94 @@
+use std::ptr;
+use crate::sys;
+
+/// The group of the expert info, see `epan/proto.h`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExpertGroup {
+    Sequence,
+    ResponseCode,
+    Undecoded,
+    Malformed,
+    Protocol,
+}
+
+/// The severity of the expert info, see `epan/proto.h`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ExpertSeverity {
+    Comment,
+    Chat,
+    Note,
+    Warn,
+    Error,
+}
+
+/// The expert info, the dissector might attach it to an item of the tree,
+/// wireshark shows it in the "Expert Information" dialog.
+#[derive(Clone, Debug)]
+pub struct ExpertDescriptor<'a> {
+    /// Like the abbrev of the field, for example `"tezos.handshake.rejected\0"`,
+    /// the user can filter by it.
+    pub name: &'a str,
+    pub summary: &'a str,
+    pub group: ExpertGroup,
+    pub severity: ExpertSeverity,
+}
+
+impl ExpertGroup {
+    fn raw(&self) -> u32 {
+        match self {
+            &ExpertGroup::Sequence => sys::PI_SEQUENCE,
+            &ExpertGroup::ResponseCode => sys::PI_RESPONSE_CODE,
+            &ExpertGroup::Undecoded => sys::PI_UNDECODED,
+            &ExpertGroup::Malformed => sys::PI_MALFORMED,
+            &ExpertGroup::Protocol => sys::PI_PROTOCOL,
+        }
+    }
+}
+
+impl ExpertSeverity {
+    fn raw(&self) -> u32 {
+        match self {
+            &ExpertSeverity::Comment => sys::PI_COMMENT,
+            &ExpertSeverity::Chat => sys::PI_CHAT,
+            &ExpertSeverity::Note => sys::PI_NOTE,
+            &ExpertSeverity::Warn => sys::PI_WARN,
+            &ExpertSeverity::Error => sys::PI_ERROR,
+        }
+    }
+}
+
+impl<'a> ExpertDescriptor<'a> {
+    /// The `id` should not move while wireshark is running.
+    pub(crate) fn info(&self, id: *mut sys::expert_field) -> sys::ei_register_info {
+        sys::ei_register_info {
+            ids: id,
+            eiinfo: sys::expert_field_info {
+                name: self.name.as_ptr() as _,
+                group: self.group.raw() as _,
+                severity: self.severity.raw() as _,
+                summary: self.summary.as_ptr() as _,
+                id: 0,
+                protocol: ptr::null(),
+                orig_severity: 0,
+                // the same as `EXPFILL` macro
+                hf_info: sys::hf_register_info {
+                    p_id: ptr::null_mut(),
+                    hfinfo: sys::header_field_info {
+                        name: ptr::null(),
+                        abbrev: ptr::null(),
+                        type_: sys::ftenum_FT_NONE,
+                        display: sys::field_display_e_BASE_NONE as _,
+                        strings: ptr::null(),
+                        bitmask: 0,
+                        blurb: ptr::null(),
+                        id: -1,
+                        parent: 0,
+                        ref_type: sys::hf_ref_type_HF_REF_TYPE_NONE,
+                        same_name_prev_id: -1,
+                        same_name_next: ptr::null_mut(),
+                    },
+                },
+            },
+        }
+    }
+}
@@ -4,10 +4,20 @@ mod sys;
 /// Covers plugin, protocol and dissector registering.
 mod plugin;
 pub use self::plugin::{
-    Plugin, NameDescriptor, FieldDescriptor, FieldDescriptorOwned, PrefFilenameDescriptor,
-    DissectorDescriptor, Dissector,
+    Plugin, NameDescriptor, FieldDescriptor, FieldDescriptorOwned, Abbrev, DissectorDescriptor,
+    Dissector,
 };
 
+/// Preferences of the protocol and their values.
+mod prefs;
+pub use self::prefs::{
+    PrefDescriptor, PrefEnumValue, TableFieldDescriptor, TableFieldKind, PrefValue, Preferences,
+};
+
+/// Expert infos, problems and notes the dissector found in the packet.
+mod expert;
+pub use self::expert::{ExpertDescriptor, ExpertGroup, ExpertSeverity};
+
 /// Wrappers around stuff that passed inside the dissector.
 pub mod dissector;
 
@@ -1,18 +1,22 @@
 use std::{
     collections::HashMap,
-    os::raw::{c_int, c_char, c_void},
+    os::raw::{c_int, c_void},
     cell::RefCell,
     ptr,
 };
 use crate::sys;
-use super::dissector::{DissectorHelper, SuperDissectorData, PacketInfo, Tree, HasFields};
+use super::{
+    dissector::{DissectorHelper, SuperDissectorData, PacketInfo, Tree, HasFields},
+    prefs::{PrefDescriptor, PrefStorage, Preferences},
+    expert::ExpertDescriptor,
+};
 
 /// Should be implemented for dissector.
 pub trait Dissector {
-    /// The only preference supported is filename.
-    /// Called when the user choose some file.
-    fn prefs_update(&mut self, filenames: Vec<&str>) {
-        let _ = filenames;
+    /// Called when the user changes some preference, and once after the preferences are loaded.
+    /// Contains the values of all preferences declared in `PrefDescriptor` array.
+    fn prefs_update(&mut self, prefs: Preferences<'_>) {
+        let _ = prefs;
     }
 
     /// Called when a new packet just arrive
@@ -24,7 +28,8 @@ pub trait Dissector {
         packet_info: &PacketInfo,
     ) -> usize;
 
-    /// Called when capturing session end.
+    /// Called when the capture file is closed, and when the preferences are changed,
+    /// right before wireshark redissects the file.
     /// The dissector is not destroyed, it might be used in the next capturing session.
     fn cleanup(&mut self);
 }
@@ -36,8 +41,10 @@ struct PluginPrivates {
     hf: Vec<sys::hf_register_info>,
     ett_handle: c_int,
     ett_info: *mut c_int,
-    pref_filenames: Vec<*const c_char>,
-    callback_registered: bool,
+    pref_values: Vec<PrefStorage>,
+    pref_enum_values: Vec<Vec<sys::enum_val_t>>,
+    expert_fields: Vec<sys::expert_field>,
+    ei: Vec<sys::ei_register_info>,
     dissector: Option<Box<dyn Dissector>>,
 }
 
@@ -52,8 +59,10 @@ impl PluginPrivates {
         hf: Vec::new(),
         ett_handle: -1,
         ett_info: ptr::null_mut(),
-        pref_filenames: Vec::new(),
-        callback_registered: false,
+        pref_values: Vec::new(),
+        pref_enum_values: Vec::new(),
+        expert_fields: Vec::new(),
+        ei: Vec::new(),
         dissector: None,
     };
 }
@@ -64,7 +73,8 @@ pub struct Plugin<'a> {
     name_descriptor: NameDescriptor<'a>,
     field_descriptors: &'a [&'a [FieldDescriptor<'a>]],
     field_descriptors_owned: Vec<FieldDescriptorOwned>,
-    filename_descriptors: &'a [PrefFilenameDescriptor<'a>],
+    pref_descriptors: &'a [PrefDescriptor<'a>],
+    expert_descriptors: &'a [ExpertDescriptor<'a>],
 }
 
 pub struct NameDescriptor<'a> {
@@ -78,6 +88,9 @@ pub enum FieldDescriptor<'a> {
     Nothing { name: &'a str, abbrev: &'a str },
     String { name: &'a str, abbrev: &'a str },
     Int64Dec { name: &'a str, abbrev: &'a str },
+    Bool { name: &'a str, abbrev: &'a str },
+    /// Number of the frame, the user can click on it to jump to the frame.
+    FrameNumber { name: &'a str, abbrev: &'a str },
 }
 
 impl<'a> FieldDescriptor<'a> {
@@ -95,15 +108,25 @@ impl<'a> FieldDescriptor<'a> {
                 name: name.to_owned(),
                 abbrev: abbrev.to_owned(),
             },
+            &FieldDescriptor::Bool { name, abbrev } => FieldDescriptorOwned::Bool {
+                name: name.to_owned(),
+                abbrev: abbrev.to_owned(),
+            },
+            &FieldDescriptor::FrameNumber { name, abbrev } => FieldDescriptorOwned::FrameNumber {
+                name: name.to_owned(),
+                abbrev: abbrev.to_owned(),
+            },
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum FieldDescriptorOwned {
     Nothing { name: String, abbrev: String },
     String { name: String, abbrev: String },
     Int64Dec { name: String, abbrev: String },
+    Bool { name: String, abbrev: String },
+    FrameNumber { name: String, abbrev: String },
 }
 
 trait Info {
@@ -164,6 +187,40 @@ impl<'a> Info for FieldDescriptor<'a> {
                     same_name_next: ptr::null_mut(),
                 },
             },
+            &FieldDescriptor::Bool { name, abbrev } => sys::hf_register_info {
+                p_id: handle,
+                hfinfo: sys::header_field_info {
+                    name: name.as_ptr() as _,
+                    abbrev: abbrev.as_ptr() as _,
+                    type_: sys::ftenum_FT_BOOLEAN,
+                    display: sys::field_display_e_BASE_NONE as _,
+                    strings: ptr::null(),
+                    bitmask: 0,
+                    blurb: ptr::null(),
+                    id: -1,
+                    parent: 0,
+                    ref_type: sys::hf_ref_type_HF_REF_TYPE_NONE,
+                    same_name_prev_id: -1,
+                    same_name_next: ptr::null_mut(),
+                },
+            },
+            &FieldDescriptor::FrameNumber { name, abbrev } => sys::hf_register_info {
+                p_id: handle,
+                hfinfo: sys::header_field_info {
+                    name: name.as_ptr() as _,
+                    abbrev: abbrev.as_ptr() as _,
+                    type_: sys::ftenum_FT_FRAMENUM,
+                    display: sys::field_display_e_BASE_NONE as _,
+                    strings: ptr::null(),
+                    bitmask: 0,
+                    blurb: ptr::null(),
+                    id: -1,
+                    parent: 0,
+                    ref_type: sys::hf_ref_type_HF_REF_TYPE_NONE,
+                    same_name_prev_id: -1,
+                    same_name_next: ptr::null_mut(),
+                },
+            },
         }
     }
 }
@@ -186,11 +243,22 @@ impl Info for FieldDescriptorOwned {
                 ref abbrev,
                 ..
             } => FieldDescriptor::Int64Dec { name, abbrev }.info(handle),
+            &FieldDescriptorOwned::Bool {
+                ref name,
+                ref abbrev,
+                ..
+            } => FieldDescriptor::Bool { name, abbrev }.info(handle),
+            &FieldDescriptorOwned::FrameNumber {
+                ref name,
+                ref abbrev,
+                ..
+            } => FieldDescriptor::FrameNumber { name, abbrev }.info(handle),
         }
     }
 }
 
-trait Abbrev {
+/// The filter name of the field, for example `"tezos.conversation_id\0"`.
+pub trait Abbrev {
     fn abbrev(&self) -> String;
 }
 
@@ -200,6 +268,8 @@ impl<'a> Abbrev for FieldDescriptor<'a> {
             &FieldDescriptor::Nothing { abbrev, .. } => abbrev.to_string(),
             &FieldDescriptor::String { abbrev, .. } => abbrev.to_string(),
             &FieldDescriptor::Int64Dec { abbrev, .. } => abbrev.to_string(),
+            &FieldDescriptor::Bool { abbrev, .. } => abbrev.to_string(),
+            &FieldDescriptor::FrameNumber { abbrev, .. } => abbrev.to_string(),
         }
     }
 }
@@ -210,19 +280,19 @@ impl Abbrev for FieldDescriptorOwned {
             &FieldDescriptorOwned::Nothing { ref abbrev, .. } => abbrev.to_string(),
             &FieldDescriptorOwned::String { ref abbrev, .. } => abbrev.clone(),
             &FieldDescriptorOwned::Int64Dec { ref abbrev, .. } => abbrev.clone(),
+            &FieldDescriptorOwned::Bool { ref abbrev, .. } => abbrev.clone(),
+            &FieldDescriptorOwned::FrameNumber { ref abbrev, .. } => abbrev.clone(),
         }
     }
 }
 
-pub struct PrefFilenameDescriptor<'a> {
-    pub name: &'a str,
-    pub title: &'a str,
-    pub description: &'a str,
-}
-
 pub struct DissectorDescriptor<'a> {
     pub display_name: &'a str,
     pub short_name: &'a str,
+    /// Default TCP ports, for example `"9732\0"`, or `"\0"` for none.
+    /// The dissector is registered in the `tcp.port` table for these ports,
+    /// the user can change them in preferences or force the dissector using "Decode As...".
+    pub tcp_ports: &'a str,
 }
 
 impl<'a> Plugin<'a> {
@@ -230,7 +300,7 @@ impl<'a> Plugin<'a> {
         dissector_descriptor: DissectorDescriptor<'a>,
         name_descriptor: NameDescriptor<'a>,
         field_descriptors: &'a [&'a [FieldDescriptor<'a>]],
-        filename_descriptors: &'a [PrefFilenameDescriptor<'a>],
+        pref_descriptors: &'a [PrefDescriptor<'a>],
     ) -> Self {
         Plugin {
             privates: RefCell::new(PluginPrivates::EMPTY),
@@ -238,10 +308,18 @@ impl<'a> Plugin<'a> {
             name_descriptor,
             field_descriptors,
             field_descriptors_owned: Vec::new(),
-            filename_descriptors,
+            pref_descriptors,
+            expert_descriptors: &[],
         }
     }
 
+    /// Declare all expert infos the dissector might add.
+    pub fn register_experts(self, expert_descriptors: &'a [ExpertDescriptor<'a>]) -> Self {
+        let mut s = self;
+        s.expert_descriptors = expert_descriptors;
+        s
+    }
+
     pub fn register_type<T>(self) -> Self
     where
         T: HasFields,
@@ -277,6 +355,21 @@ impl<'a> Plugin<'a> {
         .chain(it)
         .collect()
     }
+
+    fn experts(&self) -> HashMap<String, *mut sys::expert_field> {
+        let state = self.privates.borrow();
+
+        self.expert_descriptors
+            .iter()
+            .map(|d| d.name.to_owned())
+            .zip(
+                state
+                    .expert_fields
+                    .iter()
+                    .map(|f| f as *const sys::expert_field as *mut _),
+            )
+            .collect()
+    }
 }
 
 impl Plugin<'static> {
@@ -308,6 +401,18 @@ impl Plugin<'static> {
                 let mut state = p.privates.borrow_mut();
                 state.proto_handle = proto;
 
+                extern "C" fn cleanup() {
+                    with_plugin(|p| {
+                        let mut state = p.privates.borrow_mut();
+                        if let &mut Some(ref mut d) = &mut state.dissector {
+                            d.cleanup();
+                        }
+                    })
+                }
+
+                // called when the file is closed, and before the file is redissected
+                unsafe { sys::register_cleanup_routine(Some(cleanup)) };
+
                 let mut field_handles = {
                     let len = p.field_descriptors.iter().map(|x| x.len()).sum::<usize>();
                     let len = len + p.field_descriptors_owned.len();
@@ -337,62 +442,91 @@ impl Plugin<'static> {
                     sys::proto_register_subtree_array(&state.ett_info as _, 1);
                 }
 
-                extern "C" fn preferences_update_cb() {
-                    use std::ffi::CStr;
+                if !p.expert_descriptors.is_empty() {
+                    let state = &mut *state;
+                    // allocate all at once, the storage should not move after registration
+                    state.expert_fields = p
+                        .expert_descriptors
+                        .iter()
+                        .map(|_| sys::expert_field { ei: -1, hf: -1 })
+                        .collect();
+                    state.ei = p
+                        .expert_descriptors
+                        .iter()
+                        .zip(state.expert_fields.iter_mut())
+                        .map(|(descriptor, id)| descriptor.info(id))
+                        .collect();
+                    unsafe {
+                        let module = sys::expert_register_protocol(proto);
+                        sys::expert_register_field_array(
+                            module,
+                            state.ei.as_mut_ptr(),
+                            state.ei.len() as _,
+                        );
+                    }
+                }
 
+                extern "C" fn preferences_update_cb() {
                     with_plugin(|p| {
                         let mut state = p.privates.borrow_mut();
-                        let filenames = state
-                            .pref_filenames
+                        let state = &mut *state;
+                        let values = p
+                            .pref_descriptors
                             .iter()
-                            .map(|&p| {
-                                let s = unsafe { CStr::from_ptr(p) };
-                                s.to_str().unwrap()
-                            })
+                            .map(PrefDescriptor::name)
+                            .zip(state.pref_values.iter().map(PrefStorage::read))
                             .collect();
-                        state.dissector.as_mut().unwrap().prefs_update(filenames);
+                        let prefs = Preferences { values };
+                        state.dissector.as_mut().unwrap().prefs_update(prefs);
                     })
                 }
 
-                let filename_descriptors = &p.filename_descriptors;
-                if !filename_descriptors.is_empty() {
-                    state
-                        .pref_filenames
-                        .resize(filename_descriptors.len(), ptr::null());
+                let pref_descriptors = &p.pref_descriptors;
+                if !pref_descriptors.is_empty() {
+                    let state = &mut *state;
+                    // allocate all at once, the storage should not move after registration
+                    state.pref_values = pref_descriptors
+                        .iter()
+                        .map(PrefDescriptor::initial)
+                        .collect();
+                    state.pref_enum_values = pref_descriptors
+                        .iter()
+                        .map(PrefDescriptor::enum_values)
+                        .collect();
                     let prefs =
                         unsafe { sys::prefs_register_protocol(proto, Some(preferences_update_cb)) };
-                    for (i, d) in filename_descriptors.iter().enumerate() {
-                        unsafe {
-                            sys::prefs_register_filename_preference(
-                                prefs,
-                                d.name.as_ptr() as _,
-                                d.title.as_ptr() as _,
-                                d.description.as_ptr() as _,
-                                state.pref_filenames.get_mut(i).unwrap(),
-                                0,
-                            );
-                        }
+                    let it = pref_descriptors
+                        .iter()
+                        .zip(state.pref_values.iter_mut())
+                        .zip(state.pref_enum_values.iter());
+                    for ((d, storage), enum_values) in it {
+                        // editing the table does not always trigger the preferences callback,
+                        // so it is called after the table is updated as well
+                        let update = Some(preferences_update_cb as unsafe extern "C" fn());
+                        unsafe { d.register(prefs, storage, enum_values, update) };
                     }
                 }
             })
         }
 
-        extern "C" fn wmem_cb(
-            _allocator: *mut sys::wmem_allocator_t,
-            ev: sys::wmem_cb_event_t,
-            _data: *mut std::os::raw::c_void,
-        ) -> sys::gboolean {
-            match ev {
-                sys::_wmem_cb_event_t_WMEM_CB_DESTROY_EVENT => (),
-                _ => with_plugin(|p| {
-                    let mut state = p.privates.borrow_mut();
-                    if let &mut Some(ref mut d) = &mut state.dissector {
-                        d.cleanup();
-                    }
-                }),
-            }
-
-            0
+        fn dissect(
+            tvb: *mut sys::tvbuff_t,
+            pinfo: *mut sys::packet_info,
+            tree: *mut sys::proto_tree,
+            data: *mut c_void,
+        ) -> usize {
+            with_plugin(|p| {
+                let fields = p.fields();
+                let experts = p.experts();
+                let data = SuperDissectorData::Tcp(data as *mut sys::tcpinfo);
+                let packet_info = PacketInfo::with_data(pinfo, &data, tvb);
+                let mut helper = DissectorHelper::new(data, tvb);
+                let ett = p.privates.borrow().ett_handle;
+                let mut tree = Tree::root(fields, experts, ett, tvb, pinfo, tree);
+                let mut state = p.privates.borrow_mut();
+                let dissector = state.dissector.as_mut().unwrap();
+                dissector.consume(&mut helper, &mut tree, &packet_info)
+            })
         }
 
         extern "C" fn register_handoff() {
@@ -402,33 +536,22 @@ impl Plugin<'static> {
                 tree: *mut sys::proto_tree,
                 data: *mut c_void,
             ) -> sys::gboolean {
-                with_plugin(|p| {
-                    {
-                        let mut state = p.privates.borrow_mut();
-                        if !state.callback_registered {
-                            unsafe {
-                                sys::wmem_register_callback(
-                                    sys::wmem_file_scope(),
-                                    Some(wmem_cb),
-                                    ptr::null_mut(),
-                                );
-                            }
-                            state.callback_registered = true;
-                        }
-                    }
+                // the dissector might accept nothing yet, but ask TCP for more data,
+                // in this case the heuristic should claim the conversation as well,
+                // otherwise TCP ignores the request
+                let length = dissect(tvb, pinfo, tree, data);
+                (length != 0 || PacketInfo::new(pinfo).desegment_requested()) as _
+            }
 
-                    let fields = p.fields();
-                    let mut helper = DissectorHelper::new(
-                        SuperDissectorData::Tcp(data as *mut sys::tcpinfo),
-                        tvb,
-                    );
-                    let mut tree = Tree::root(fields, p.privates.borrow().ett_handle, tvb, tree);
-                    let packet_info = PacketInfo::new(pinfo);
-                    let mut state = p.privates.borrow_mut();
-                    let dissector = state.dissector.as_mut().unwrap();
-                    let processed_length = dissector.consume(&mut helper, &mut tree, &packet_info);
-                    processed_length as _
-                })
+            // called by the `tcp.port` table, either for the configured ports,
+            // or when the user choose the dissector in "Decode As..."
+            extern "C" fn port_dissector(
+                tvb: *mut sys::tvbuff_t,
+                pinfo: *mut sys::packet_info,
+                tree: *mut sys::proto_tree,
+                data: *mut c_void,
+            ) -> c_int {
+                dissect(tvb, pinfo, tree, data) as _
             }
 
             with_plugin(|p| {
@@ -443,6 +566,14 @@ impl Plugin<'static> {
                         proto_handle,
                         sys::heuristic_enable_e_HEURISTIC_ENABLE,
                     );
+                    let handle = sys::create_dissector_handle(Some(port_dissector), proto_handle);
+                    // registers the preference "Tezos TCP port(s)" as well
+                    sys::dissector_add_uint_range_with_preference(
+                        "tcp.port\0".as_ptr() as _,
+                        p.dissector_descriptor.tcp_ports.as_ptr() as _,
+                        handle,
+                    );
+                    sys::dissector_add_for_decode_as("tcp.port\0".as_ptr() as _, handle);
                 }
             })
         }
@@ -0,0 +1,577 @@
+use std::{
+    os::raw::{c_int, c_uint, c_char, c_void},
+    ops::RangeInclusive,
+    ffi::CStr,
+    mem, ptr, slice,
+};
+use crate::sys;
+
+/// Describes a preference of the protocol, the preferences appear
+/// in Edit -> Preferences -> Protocols -> <protocol>,
+/// and can be set from the command line `-o <filter_name>.<name>:<value>`.
+/// All strings should be null terminated.
+pub enum PrefDescriptor<'a> {
+    Bool {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+        default: bool,
+    },
+    Uint {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+        default: u32,
+    },
+    Enum {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+        default: i32,
+        values: &'a [PrefEnumValue<'a>],
+    },
+    String {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+        default: &'a str,
+    },
+    Filename {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+    },
+    Directory {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+    },
+    /// The range like `"9732,19732-19740\0"`, each value should not exceed `max`.
+    Range {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+        default: &'a str,
+        max: u32,
+    },
+    /// User accessible table, the user can add, remove and edit rows in the GUI.
+    /// The rows are stored in the file `filename` in the wireshark profile.
+    Table {
+        name: &'a str,
+        title: &'a str,
+        description: &'a str,
+        filename: &'a str,
+        fields: &'a [TableFieldDescriptor<'a>],
+    },
+}
+
+/// The column of `PrefDescriptor::Table`.
+pub struct TableFieldDescriptor<'a> {
+    pub name: &'a str,
+    pub title: &'a str,
+    pub description: &'a str,
+    pub kind: TableFieldKind,
+}
+
+/// How the GUI presents the column, the value is always a string,
+/// the boolean column is `"TRUE"` or `"FALSE"`.
+#[derive(Clone, Copy, Debug)]
+pub enum TableFieldKind {
+    String,
+    Filename,
+    Bool,
+}
+
+/// The variant of `PrefDescriptor::Enum`.
+pub struct PrefEnumValue<'a> {
+    pub name: &'a str,
+    pub description: &'a str,
+    pub value: i32,
+}
+
+impl<'a> PrefDescriptor<'a> {
+    pub fn name(&self) -> &'a str {
+        match self {
+            &PrefDescriptor::Bool { name, .. } => name,
+            &PrefDescriptor::Uint { name, .. } => name,
+            &PrefDescriptor::Enum { name, .. } => name,
+            &PrefDescriptor::String { name, .. } => name,
+            &PrefDescriptor::Filename { name, .. } => name,
+            &PrefDescriptor::Directory { name, .. } => name,
+            &PrefDescriptor::Range { name, .. } => name,
+            &PrefDescriptor::Table { name, .. } => name,
+        }
+    }
+
+    pub(crate) fn enum_values(&self) -> Vec<sys::enum_val_t> {
+        match self {
+            &PrefDescriptor::Enum { values, .. } => values
+                .iter()
+                .map(|v| sys::enum_val_t {
+                    name: v.name.as_ptr() as _,
+                    description: v.description.as_ptr() as _,
+                    value: v.value,
+                })
+                // wireshark expects the array terminated by the null entry
+                .chain(std::iter::once(sys::enum_val_t {
+                    name: ptr::null(),
+                    description: ptr::null(),
+                    value: 0,
+                }))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub(crate) fn initial(&self) -> PrefStorage {
+        match self {
+            &PrefDescriptor::Bool { default, .. } => PrefStorage::Bool(default as _),
+            &PrefDescriptor::Uint { default, .. } => PrefStorage::Uint(default as _),
+            &PrefDescriptor::Enum { default, .. } => PrefStorage::Enum(default as _),
+            // wireshark copies the default string
+            &PrefDescriptor::String { default, .. } => PrefStorage::String(default.as_ptr() as _),
+            &PrefDescriptor::Filename { .. } | &PrefDescriptor::Directory { .. } => {
+                PrefStorage::String(ptr::null())
+            },
+            &PrefDescriptor::Range { .. } => PrefStorage::Range(ptr::null_mut()),
+            &PrefDescriptor::Table { fields, .. } => PrefStorage::Table {
+                uat: ptr::null_mut(),
+                records: ptr::null_mut(),
+                count: 0,
+                width: fields.len(),
+                fields: table_fields(fields),
+            },
+        }
+    }
+
+    /// The storage should not move after this call, wireshark writes the value there.
+    /// The `post_update` is called when the user edits the table.
+    pub(crate) unsafe fn register(
+        &self,
+        module: *mut sys::module_t,
+        storage: &mut PrefStorage,
+        enum_values: &[sys::enum_val_t],
+        post_update: sys::uat_post_update_cb_t,
+    ) {
+        match (self, storage) {
+            (
+                &PrefDescriptor::Bool {
+                    name,
+                    title,
+                    description,
+                    ..
+                },
+                &mut PrefStorage::Bool(ref mut var),
+            ) => sys::prefs_register_bool_preference(
+                module,
+                name.as_ptr() as _,
+                title.as_ptr() as _,
+                description.as_ptr() as _,
+                var,
+            ),
+            (
+                &PrefDescriptor::Uint {
+                    name,
+                    title,
+                    description,
+                    ..
+                },
+                &mut PrefStorage::Uint(ref mut var),
+            ) => sys::prefs_register_uint_preference(
+                module,
+                name.as_ptr() as _,
+                title.as_ptr() as _,
+                description.as_ptr() as _,
+                10,
+                var,
+            ),
+            (
+                &PrefDescriptor::Enum {
+                    name,
+                    title,
+                    description,
+                    ..
+                },
+                &mut PrefStorage::Enum(ref mut var),
+            ) => sys::prefs_register_enum_preference(
+                module,
+                name.as_ptr() as _,
+                title.as_ptr() as _,
+                description.as_ptr() as _,
+                var,
+                enum_values.as_ptr(),
+                0,
+            ),
+            (
+                &PrefDescriptor::String {
+                    name,
+                    title,
+                    description,
+                    ..
+                },
+                &mut PrefStorage::String(ref mut var),
+            ) => sys::prefs_register_string_preference(
+                module,
+                name.as_ptr() as _,
+                title.as_ptr() as _,
+                description.as_ptr() as _,
+                var,
+            ),
+            (
+                &PrefDescriptor::Filename {
+                    name,
+                    title,
+                    description,
+                },
+                &mut PrefStorage::String(ref mut var),
+            ) => sys::prefs_register_filename_preference(
+                module,
+                name.as_ptr() as _,
+                title.as_ptr() as _,
+                description.as_ptr() as _,
+                var,
+                0,
+            ),
+            (
+                &PrefDescriptor::Directory {
+                    name,
+                    title,
+                    description,
+                },
+                &mut PrefStorage::String(ref mut var),
+            ) => sys::prefs_register_directory_preference(
+                module,
+                name.as_ptr() as _,
+                title.as_ptr() as _,
+                description.as_ptr() as _,
+                var,
+            ),
+            (
+                &PrefDescriptor::Range {
+                    name,
+                    title,
+                    description,
+                    default,
+                    max,
+                },
+                &mut PrefStorage::Range(ref mut var),
+            ) => {
+                let _ = sys::range_convert_str(
+                    sys::wmem_epan_scope(),
+                    var,
+                    default.as_ptr() as _,
+                    max,
+                );
+                sys::prefs_register_range_preference(
+                    module,
+                    name.as_ptr() as _,
+                    title.as_ptr() as _,
+                    description.as_ptr() as _,
+                    var,
+                    max,
+                )
+            },
+            (
+                &PrefDescriptor::Table {
+                    name,
+                    title,
+                    description,
+                    filename,
+                    ..
+                },
+                &mut PrefStorage::Table {
+                    ref mut uat,
+                    ref mut records,
+                    ref mut count,
+                    ref mut width,
+                    ref mut fields,
+                },
+            ) => {
+                *uat = sys::uat_new(
+                    title.as_ptr() as _,
+                    record_size(*width) as _,
+                    filename.as_ptr() as _,
+                    1,
+                    records as *mut *mut c_void as _,
+                    count,
+                    sys::UAT_AFFECTS_DISSECTION as _,
+                    ptr::null(),
+                    Some(record_copy),
+                    None,
+                    Some(record_free),
+                    post_update,
+                    None,
+                    fields.as_mut_ptr(),
+                );
+                sys::prefs_register_uat_preference(
+                    module,
+                    name.as_ptr() as _,
+                    title.as_ptr() as _,
+                    description.as_ptr() as _,
+                    *uat,
+                )
+            },
+            _ => panic!("the preference storage does not match the descriptor"),
+        }
+    }
+}
+
+/// The place where wireshark keeps the current value of the preference.
+pub(crate) enum PrefStorage {
+    Bool(sys::gboolean),
+    Uint(c_uint),
+    Enum(c_int),
+    String(*const c_char),
+    Range(*mut sys::range_t),
+    // wireshark owns the array of records and keeps its pointer and length here
+    Table {
+        uat: *mut sys::uat_t,
+        records: *mut c_void,
+        count: c_uint,
+        width: usize,
+        fields: Vec<sys::uat_field_t>,
+    },
+}
+
+impl PrefStorage {
+    pub(crate) fn read(&self) -> PrefValue<'_> {
+        match self {
+            &PrefStorage::Bool(ref v) => PrefValue::Bool(*v != 0),
+            &PrefStorage::Uint(ref v) => PrefValue::Uint(*v as _),
+            &PrefStorage::Enum(ref v) => PrefValue::Enum(*v as _),
+            &PrefStorage::String(ref v) => {
+                if v.is_null() {
+                    PrefValue::String("")
+                } else {
+                    let s = unsafe { CStr::from_ptr(*v) };
+                    PrefValue::String(s.to_str().unwrap_or(""))
+                }
+            },
+            &PrefStorage::Range(ref v) => {
+                if v.is_null() {
+                    PrefValue::Range(Vec::new())
+                } else {
+                    let ranges = unsafe {
+                        let range = &**v;
+                        let first = &range.ranges as *const _ as *const sys::range_admin_t;
+                        slice::from_raw_parts(first, range.nranges as _)
+                    };
+                    PrefValue::Range(ranges.iter().map(|r| r.low..=r.high).collect())
+                }
+            },
+            &PrefStorage::Table {
+                ref records,
+                ref count,
+                ref width,
+                ..
+            } => {
+                let rows = (0..(*count as usize))
+                    .map(|i| {
+                        let record = unsafe {
+                            (*records as *const u8).add(i * record_size(*width)) as *const Record
+                        };
+                        (0..*width)
+                            .map(|j| {
+                                let value = unsafe { *Record::slot(record, j) };
+                                if value.is_null() {
+                                    ""
+                                } else {
+                                    let s = unsafe { CStr::from_ptr(value) };
+                                    s.to_str().unwrap_or("")
+                                }
+                            })
+                            .collect()
+                    })
+                    .collect();
+                PrefValue::Table(rows)
+            },
+        }
+    }
+}
+
+// The record of the table is a number of fields followed by pointers to null terminated strings,
+// the strings are allocated by glib. Wireshark allocates new record filled with zeros,
+// so the number of fields is written again when any field is set.
+#[repr(C)]
+struct Record {
+    width: usize,
+}
+
+impl Record {
+    unsafe fn slot(record: *const Record, index: usize) -> *mut *mut c_char {
+        (record.add(1) as *mut *mut c_char).add(index)
+    }
+}
+
+fn record_size(width: usize) -> usize {
+    mem::size_of::<Record>() + width * mem::size_of::<*mut c_char>()
+}
+
+fn table_fields(fields: &[TableFieldDescriptor]) -> Vec<sys::uat_field_t> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(index, d)| {
+            let mut field: sys::uat_field_t = unsafe { mem::zeroed() };
+            field.name = d.name.as_ptr() as _;
+            field.title = d.title.as_ptr() as _;
+            field.desc = d.description.as_ptr() as _;
+            field.mode = match d.kind {
+                TableFieldKind::String => sys::_uat_text_mode_t_PT_TXTMOD_STRING,
+                TableFieldKind::Filename => sys::_uat_text_mode_t_PT_TXTMOD_FILENAME,
+                TableFieldKind::Bool => sys::_uat_text_mode_t_PT_TXTMOD_BOOL,
+            };
+            field.cb.set = Some(record_set);
+            field.cb.tostr = Some(record_tostr);
+            field.cbdata.set = fields.len() as *const c_void;
+            field.fld_data = index as *const c_void;
+            field
+        })
+        // wireshark expects the array terminated by the zeroed entry
+        .chain(std::iter::once(unsafe { mem::zeroed() }))
+        .collect()
+}
+
+extern "C" fn record_set(
+    record: *mut c_void,
+    value: *const c_char,
+    length: c_uint,
+    set_data: *const c_void,
+    field_data: *const c_void,
+) {
+    let record = record as *mut Record;
+    unsafe {
+        (*record).width = set_data as usize;
+        let slot = Record::slot(record, field_data as usize);
+        sys::g_free(*slot as _);
+        *slot = sys::g_strndup(value, length as _);
+    }
+}
+
+extern "C" fn record_tostr(
+    record: *mut c_void,
+    out: *mut *mut c_char,
+    out_length: *mut c_uint,
+    _tostr_data: *const c_void,
+    field_data: *const c_void,
+) {
+    let record = record as *const Record;
+    unsafe {
+        let value = if (*record).width > field_data as usize {
+            *Record::slot(record, field_data as usize)
+        } else {
+            ptr::null_mut()
+        };
+        if value.is_null() {
+            *out = sys::g_strdup("\0".as_ptr() as _);
+            *out_length = 0;
+        } else {
+            *out = sys::g_strdup(value);
+            *out_length = CStr::from_ptr(value).to_bytes().len() as _;
+        }
+    }
+}
+
+extern "C" fn record_copy(
+    destination: *mut c_void,
+    source: *const c_void,
+    _length: sys::size_t,
+) -> *mut c_void {
+    let (destination, source) = (destination as *mut Record, source as *const Record);
+    unsafe {
+        let width = (*source).width;
+        (*destination).width = width;
+        for i in 0..width {
+            let value = *Record::slot(source, i);
+            *Record::slot(destination, i) = if value.is_null() {
+                ptr::null_mut()
+            } else {
+                sys::g_strdup(value)
+            };
+        }
+    }
+    destination as _
+}
+
+extern "C" fn record_free(record: *mut c_void) {
+    let record = record as *mut Record;
+    unsafe {
+        for i in 0..(*record).width {
+            let slot = Record::slot(record, i);
+            sys::g_free(*slot as _);
+            *slot = ptr::null_mut();
+        }
+    }
+}
+
+/// The value of the preference.
+/// The filename and directory preferences are strings.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PrefValue<'a> {
+    Bool(bool),
+    Uint(u32),
+    Enum(i32),
+    String(&'a str),
+    Range(Vec<RangeInclusive<u32>>),
+    /// Rows of the table, each row has the value of each field.
+    Table(Vec<Vec<&'a str>>),
+}
+
+/// Current values of all preferences, passed to `Dissector::prefs_update`.
+pub struct Preferences<'a> {
+    pub(crate) values: Vec<(&'a str, PrefValue<'a>)>,
+}
+
+impl<'a> Preferences<'a> {
+    /// The name is the same as in the descriptor, the null terminator is optional.
+    pub fn get(&self, name: &str) -> Option<&PrefValue<'a>> {
+        let name = name.trim_end_matches('\0');
+        self.values
+            .iter()
+            .find(|&&(ref n, _)| n.trim_end_matches('\0') == name)
+            .map(|&(_, ref v)| v)
+    }
+
+    pub fn bool(&self, name: &str) -> Option<bool> {
+        match self.get(name) {
+            Some(&PrefValue::Bool(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn uint(&self, name: &str) -> Option<u32> {
+        match self.get(name) {
+            Some(&PrefValue::Uint(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn enum_value(&self, name: &str) -> Option<i32> {
+        match self.get(name) {
+            Some(&PrefValue::Enum(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Value of string, filename or directory preference.
+    pub fn string(&self, name: &str) -> Option<&'a str> {
+        match self.get(name) {
+            Some(&PrefValue::String(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn range(&self, name: &str) -> Option<&[RangeInclusive<u32>]> {
+        match self.get(name) {
+            Some(&PrefValue::Range(ref v)) => Some(v.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn table(&self, name: &str) -> Option<&[Vec<&'a str>]> {
+        match self.get(name) {
+            Some(&PrefValue::Table(ref v)) => Some(v.as_ref()),
+            _ => None,
+        }
+    }
+}